@@ -1,19 +1,27 @@
 #![allow(unused_must_use)]
 use crate::{
+    cart::{Cart, Sprite, RESOLUTIONS},
     codewindow::CodeWindow,
+    debugwindow::DebugWindow,
     gamewindow::GameWindow,
-    leftpanel::{ObjectSelector, SpritesSelector},
+    leftpanel::{ObjectSelector, SceneSelector, SpritesSelector},
+    mapexport,
     mapwindow::MapWindow,
+    pico8, profiler,
     propertieswindow::PropertiesWindow,
-    spriteswindow::{sized_toolbtn_ui, Sprite, SpritesWindow},
+    spritesheet,
+    spriteswindow::{sized_toolbtn_ui, SpritesWindow},
     tab::Tab,
-    zvm::{self, ZEvent, ZVMState, STATE_PTR, ZVM},
+    tiled,
+    watchwindow::WatchWindow,
+    webexport,
 };
 use array2d::Array2D;
 use egui::{
     pos2, vec2, Align2, Color32, Id, Key, LayerId, Layout, Painter, Pos2, Rect, RichText, Sense,
     Stroke, Vec2, Widget,
 };
+use image::{Rgba, RgbaImage};
 use std::{cell::RefCell, fmt::Debug, mem, rc::Rc, sync::Mutex};
 
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
@@ -36,29 +44,26 @@ pub struct FanzApp<'a> {
     pub selectedsprite: usize,
     #[serde(skip)]
     pub selectedobject: usize,
+    /// the scene the map and object list show
+    #[serde(skip)]
+    pub scene: usize,
 
     #[serde(skip)]
-    pub gamewindow: Rc<RefCell<GameWindow<'a>>>,
+    pub gamewindow: Rc<RefCell<GameWindow>>,
+    #[serde(skip)]
+    pub debugwindow: Rc<RefCell<DebugWindow>>,
+    #[serde(skip)]
+    pub watchwindow: Rc<RefCell<WatchWindow>>,
     #[serde(skip)]
     pub leftpanelselected: usize,
     #[serde(skip)]
     pub leftpanel: Rc<RefCell<Vec<Box<dyn Tab<'a>>>>>,
     #[serde(skip)]
     pub output: Vec<RichText>,
+    /// folder with the web player `build_web.sh` made, see `webexport`
+    #[serde(default)]
+    pub webbuild: Option<std::path::PathBuf>,
 }
-#[derive(serde::Deserialize, serde::Serialize)]
-pub struct Cart {
-    pub sprites: Vec<Sprite>,
-    pub map: Array2D<Option<usize>>,
-    pub objects: Vec<EditorObject>,
-}
-#[derive(serde::Deserialize, serde::Serialize)]
-pub struct EditorObject {
-    pub pos: Pos2,
-    pub name: String,
-    pub script: String,
-}
-
 impl<'a> Default for FanzApp<'a> {
     fn default() -> Self {
         dbg!(vec![
@@ -70,21 +75,22 @@ impl<'a> Default for FanzApp<'a> {
             selectedsprite: 0,
             output: vec![RichText::new("fan-z launched").color(Color32::GREEN)],
             gamewindow: Rc::new(RefCell::new(GameWindow::default())),
+            debugwindow: Rc::new(RefCell::new(DebugWindow::default())),
+            watchwindow: Rc::new(RefCell::new(WatchWindow::default())),
             codewindow: Rc::new(RefCell::new(CodeWindow::default())),
             mapwindow: Rc::new(RefCell::new(MapWindow::default())),
             propertieswindow: Rc::new(RefCell::new(PropertiesWindow::default())),
             spriteswindow: Rc::new(RefCell::new(SpritesWindow::default())),
             leftpanel: Rc::new(RefCell::new(vec![
                 Box::new(SpritesSelector),
-                Box::new(ObjectSelector),
+                Box::new(SceneSelector::default()),
+                Box::new(ObjectSelector::default()),
             ])),
             leftpanelselected: 0,
             selectedobject: 0,
-            cart: Cart {
-                map: Array2D::filled_with(None, 8, 8),
-                sprites: vec![],
-                objects: vec![],
-            },
+            scene: 0,
+            cart: Cart::default(),
+            webbuild: None,
         }
     }
 }
@@ -107,6 +113,68 @@ impl<'a> FanzApp<'a> {
 
         Default::default()
     }
+
+    /// carts are saved as json, the same format the player and web export read
+    fn open_cart_dialog(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = match rfd::FileDialog::new()
+                .add_filter("fan-z cart", &["json"])
+                .pick_file()
+            {
+                Some(p) => p,
+                None => return,
+            };
+            let cart = std::fs::read_to_string(&path)
+                .map_err(|e| format!("could not read {}: {}", path.display(), e))
+                .and_then(|json| {
+                    serde_json::from_str::<Cart>(&json).map_err(|e| format!("bad cart: {}", e))
+                });
+            match cart {
+                Ok(cart) => {
+                    self.cart = cart;
+                    self.selectedsprite = 0;
+                    self.selectedobject = 0;
+                    self.scene = 0;
+                    self.output.push(
+                        RichText::new(format!("opened {}", path.display())).color(Color32::GREEN),
+                    );
+                }
+                Err(e) => self.output.push(RichText::new(e).color(Color32::RED)),
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        self.output
+            .push(RichText::new("opening carts is not supported on web").color(Color32::RED));
+    }
+    fn save_cart_dialog(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let path = match rfd::FileDialog::new()
+                .add_filter("fan-z cart", &["json"])
+                .set_file_name("cart.json")
+                .save_file()
+            {
+                Some(p) => p,
+                None => return,
+            };
+            let result = serde_json::to_string_pretty(&self.cart)
+                .map_err(|e| format!("could not save cart: {}", e))
+                .and_then(|json| {
+                    std::fs::write(&path, json)
+                        .map_err(|e| format!("could not write {}: {}", path.display(), e))
+                });
+            match result {
+                Ok(()) => self
+                    .output
+                    .push(RichText::new(format!("saved {}", path.display())).color(Color32::GREEN)),
+                Err(e) => self.output.push(RichText::new(e).color(Color32::RED)),
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        self.output
+            .push(RichText::new("saving carts is not supported on web").color(Color32::RED));
+    }
 }
 
 impl<'a> eframe::App for FanzApp<'a> {
@@ -116,57 +184,121 @@ impl<'a> eframe::App for FanzApp<'a> {
 
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         ctx.set_visuals(egui::style::Visuals::dark());
+        self.scene = self.scene.min(self.cart.scenes.len().saturating_sub(1));
+        {
+            let mut win = self.gamewindow.borrow_mut();
+            win.shortcuts(&self.cart, ctx);
+            win.autoreload(&self.cart);
+            win.syncbreakpoints(&self.cart);
+            win.setwatching(self.watchwindow.borrow().enabled);
+            win.poll(&mut self.output, ctx);
+        }
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             // The top panel is often a good place for a menu bar:
             egui::menu::bar(ui, |ui| {
                 ui.menu_button("File", |ui| {
+                    if ui.button("Open cart...").clicked() {
+                        self.open_cart_dialog();
+                        ui.close_menu();
+                    }
+                    if ui.button("Save cart as...").clicked() {
+                        self.save_cart_dialog();
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    if ui.button("Import pico-8 cart...").clicked() {
+                        pico8::import_dialog(self);
+                        ui.close_menu();
+                    }
+                    if ui.button("Import sprite sheet...").clicked() {
+                        let (size, quantized) = {
+                            let win = self.spriteswindow.borrow();
+                            (win.importsize, win.quantize)
+                        };
+                        spritesheet::import_dialog(self, size, quantized);
+                        ui.close_menu();
+                    }
+                    if ui.button("Export sprite sheet...").clicked() {
+                        let columns = self.spriteswindow.borrow().sheetcolumns;
+                        spritesheet::export_dialog(self, columns);
+                        ui.close_menu();
+                    }
+                    if ui.button("Export map as PNG...").clicked() {
+                        let (scale, grid) = {
+                            let win = self.mapwindow.borrow();
+                            (win.exportscale, win.exportgrid)
+                        };
+                        mapexport::export_dialog(self, scale, grid);
+                        ui.close_menu();
+                    }
+                    if ui.button("Import Tiled map...").clicked() {
+                        let loadimages = self.mapwindow.borrow().tiledimages;
+                        tiled::import_dialog(self, loadimages);
+                        ui.close_menu();
+                    }
+                    if ui.button("Export Tiled map...").clicked() {
+                        let columns = self.spriteswindow.borrow().sheetcolumns;
+                        tiled::export_dialog(self, columns);
+                        ui.close_menu();
+                    }
+                    if ui.button("Export web player...").clicked() {
+                        webexport::export_dialog(self);
+                        ui.close_menu();
+                    }
+                    ui.separator();
                     if ui.button("Quit").clicked() {
                         frame.quit();
                     }
                 });
+                ui.menu_button("Cart", |ui| {
+                    ui.label("Resolution");
+                    for (w, h) in RESOLUTIONS {
+                        let selected = self.cart.width == w && self.cart.height == h;
+                        if ui.radio(selected, format!("{}x{}", w, h)).clicked() {
+                            self.cart.width = w;
+                            self.cart.height = h;
+                        }
+                    }
+                    ui.horizontal(|ui| {
+                        ui.add(egui::DragValue::new(&mut self.cart.width).clamp_range(8..=1024));
+                        ui.label("x");
+                        ui.add(egui::DragValue::new(&mut self.cart.height).clamp_range(8..=1024));
+                    });
+                    ui.separator();
+                    ui.label("Script budget per call");
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::DragValue::new(&mut self.cart.budget.instructions)
+                                .clamp_range(1..=u64::MAX)
+                                .speed(100.0),
+                        );
+                        ui.label("instructions");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.add(
+                            egui::DragValue::new(&mut self.cart.budget.millis)
+                                .clamp_range(1..=60_000),
+                        );
+                        ui.label("ms");
+                    });
+                });
                 ui.menu_button("View", |ui| {
                     if ui.button("Sprite Selector").clicked() {
                         self.leftpanel.borrow_mut().push(Box::new(SpritesSelector))
                     }
+                    if ui.button("Scene Selector").clicked() {
+                        self.leftpanel
+                            .borrow_mut()
+                            .push(Box::new(SceneSelector::default()))
+                    }
                     if ui.button("Object Selector").clicked() {
-                        self.leftpanel.borrow_mut().push(Box::new(ObjectSelector))
+                        self.leftpanel
+                            .borrow_mut()
+                            .push(Box::new(ObjectSelector::default()))
                     }
                 });
                 let tmp = &mut self.gamewindow.clone();
-                let mut win = tmp.borrow_mut();
-                let game = &mut win.game;
-                match game {
-                    Some(_) => {
-                        if ui.button("Stop").clicked() {
-                            *game = None;
-                        }
-                    }
-                    None => {
-                        if ui.button("Play").clicked() {
-                            let state = unsafe {
-                                if STATE_PTR.is_null() {
-                                    STATE_PTR = Box::leak(Box::new(ZVMState {
-                                        buffer: vec![],
-                                        keys: vec![],
-                                    }));
-                                    // cry about it
-                                }
-                                &mut *STATE_PTR
-                            };
-                            state.buffer.clear();
-                            state.keys.clear();
-                            *game = match GameWindow::startgame(&mut self.cart) {
-                                Ok(vm) => Some(vm),
-                                Err(e) => {
-                                    self.output
-                                        .push(RichText::new(e.to_string()).color(Color32::RED));
-                                    None
-                                }
-                            };
-                            win.enabled = true;
-                        }
-                    }
-                }
+                tmp.borrow_mut().controls_ui(&self.cart, ui);
             });
         });
         egui::TopBottomPanel::bottom("console_output")
@@ -233,6 +365,21 @@ impl<'a> eframe::App for FanzApp<'a> {
                         "Game",
                         &mut self.gamewindow.clone().borrow_mut().enabled,
                     );
+                    toolbtn_ui(
+                        ui,
+                        "Properties",
+                        &mut self.propertieswindow.clone().borrow_mut().enabled,
+                    );
+                    toolbtn_ui(
+                        ui,
+                        "Debug",
+                        &mut self.debugwindow.clone().borrow_mut().enabled,
+                    );
+                    toolbtn_ui(
+                        ui,
+                        "Watch",
+                        &mut self.watchwindow.clone().borrow_mut().enabled,
+                    );
                     toolbtn_ui(ui, "Map", &mut self.mapwindow.clone().borrow_mut().enabled);
                     toolbtn_ui(
                         ui,
@@ -246,14 +393,46 @@ impl<'a> eframe::App for FanzApp<'a> {
         let mut win = tmp.borrow_mut();
         if win.enabled {
             egui::Window::new("Game")
+                .resizable(true)
                 .resize(|r| {
-                    r.resizable(false);
-                    r.min_size(vec2(160.0, 120.0))
+                    r.min_size(vec2(self.cart.width as f32, self.cart.height as f32))
+                        .default_size(vec2(
+                            self.cart.width as f32 * 2.0,
+                            self.cart.height as f32 * 2.0,
+                        ))
                 })
                 .show(ctx, |ui| {
-                    win.ui(self, ui);
+                    win.settings_ui(ui);
+                    if win.profiler.enabled && ui.button("Save trace...").clicked() {
+                        profiler::export_dialog(&win.profiler, &mut self.output);
+                    }
+                    win.ui(&self.cart, ui);
+                });
+        }
+        if let Some(object) = win.jumpto.take() {
+            // the game stopped at a breakpoint, show where
+            self.selectedobject = object;
+            self.codewindow.borrow_mut().enabled = true;
+            self.debugwindow.borrow_mut().enabled = true;
+        }
+        let tmp = self.debugwindow.clone();
+        let mut debugwin = tmp.borrow_mut();
+        if debugwin.enabled {
+            egui::Window::new("Debugger")
+                .resizable(true)
+                .show(ctx, |ui| {
+                    debugwin.ui(&mut win, ui);
                 });
         }
+        let tmp = self.watchwindow.clone();
+        let mut watchwin = tmp.borrow_mut();
+        if watchwin.enabled {
+            egui::Window::new("Watch").resizable(true).show(ctx, |ui| {
+                watchwin.ui(&mut win, ui);
+            });
+        }
+        // the code window reads the breakpoint the game is stopped at
+        drop(win);
         let tmp = self.codewindow.clone();
         let mut win = tmp.borrow_mut();
         if win.enabled {
@@ -264,6 +443,15 @@ impl<'a> eframe::App for FanzApp<'a> {
                     win.ui(self, ui);
                 });
         }
+        let tmp = self.propertieswindow.clone();
+        let mut win = tmp.borrow_mut();
+        if win.enabled {
+            egui::Window::new("Properties")
+                .resizable(true)
+                .show(ctx, |ui| {
+                    win.ui(self, ui);
+                });
+        }
         let tmp = self.mapwindow.clone();
         let mut win = tmp.borrow_mut();
         if win.enabled {
@@ -309,6 +497,53 @@ pub fn draw_sprite(painter: &Painter, rect: Rect, spr: &Sprite) {
     }
 }
 
+/// same as `draw_sprite`, but onto an image. pixels are alpha blended over whatever is already there
+pub fn draw_sprite_image(img: &mut RgbaImage, start: (u32, u32), size: (u32, u32), spr: &Sprite) {
+    let spritesize = (spr.data.num_rows() as u32, spr.data.num_columns() as u32);
+    if spritesize.0 == 0 || spritesize.1 == 0 {
+        return;
+    }
+
+    // bigger sprites get shrunk to fit, so they stay inside `size` like `draw_sprite`
+    let scale_factor =
+        (size.0 as f32 / spritesize.0 as f32).min(size.1 as f32 / spritesize.1 as f32);
+    let drawn = (
+        ((spritesize.0 as f32 * scale_factor) as u32).min(size.0),
+        ((spritesize.1 as f32 * scale_factor) as u32).min(size.1),
+    );
+    for ox in 0..drawn.0 {
+        for oy in 0..drawn.1 {
+            let x = ((ox as f32 / scale_factor) as usize).min(spr.data.num_rows() - 1);
+            let y = ((oy as f32 / scale_factor) as usize).min(spr.data.num_columns() - 1);
+            let color = spr.data.get(x, y).unwrap();
+            if color.a() == 0 {
+                continue;
+            }
+            let (ix, iy) = (start.0 + ox, start.1 + oy);
+            if ix < img.width() && iy < img.height() {
+                let under = img.get_pixel(ix, iy).0;
+                img.put_pixel(ix, iy, Rgba(blend(*color, under)));
+            }
+        }
+    }
+}
+/// `color` over `under`, both unmultiplied
+pub fn blend(color: Color32, under: [u8; 4]) -> [u8; 4] {
+    let over = color.to_srgba_unmultiplied();
+    let a = over[3] as f32 / 255.0;
+    let ua = under[3] as f32 / 255.0 * (1.0 - a);
+    let outa = a + ua;
+    if outa <= 0.0 {
+        return [0, 0, 0, 0];
+    }
+    let mut out = [0u8; 4];
+    for i in 0..3 {
+        out[i] = ((over[i] as f32 * a + under[i] as f32 * ua) / outa).round() as u8;
+    }
+    out[3] = (outa * 255.0).round() as u8;
+    out
+}
+
 pub fn toolbtn_ui(ui: &mut egui::Ui, text: &str, on: &mut bool) -> egui::Response {
     // if ui.is_rect_visible(rect)
     let size = ui.available_size().x;
@@ -344,3 +579,30 @@ pub fn toolbtn_ui(ui: &mut egui::Ui, text: &str, on: &mut bool) -> egui::Respons
 
     response
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draw_sprite_image_fits_the_cell() {
+        let mut img = RgbaImage::new(24, 24);
+        let big = Sprite {
+            data: Array2D::filled_with(Color32::RED, 16, 16),
+            flags: 0,
+        };
+        draw_sprite_image(&mut img, (8, 8), (8, 8), &big);
+        for (x, y, pixel) in img.enumerate_pixels() {
+            let inside = (8..16).contains(&x) && (8..16).contains(&y);
+            assert_eq!(pixel.0[3] > 0, inside, "{} {}", x, y);
+        }
+        // small ones still get blown up to fill it
+        let mut img = RgbaImage::new(8, 8);
+        let small = Sprite {
+            data: Array2D::filled_with(Color32::RED, 2, 2),
+            flags: 0,
+        };
+        draw_sprite_image(&mut img, (0, 0), (8, 8), &small);
+        assert!(img.pixels().all(|p| p.0 == [255, 0, 0, 255]));
+    }
+}
@@ -0,0 +1,58 @@
+#![warn(clippy::all, rust_2018_idioms)]
+#![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
+
+use fan_z::PlayerApp;
+
+const USAGE: &str = "usage: fan-z-player <cart.json> [--scale N] [--fullscreen]";
+
+fn main() {
+    // Log to stdout (if you run with `RUST_LOG=debug`).
+    tracing_subscriber::fmt::init();
+
+    let mut path = None;
+    let mut scale = 4.0;
+    let mut fullscreen = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scale" => match args.next().and_then(|s| s.parse::<u32>().ok()) {
+                Some(s) if s > 0 => scale = s as f32,
+                _ => exit(USAGE),
+            },
+            "--fullscreen" => fullscreen = true,
+            "-h" | "--help" => exit(USAGE),
+            _ => path = Some(arg),
+        }
+    }
+    let path = match path {
+        Some(p) => p,
+        None => exit(USAGE),
+    };
+
+    let app = match std::fs::read_to_string(&path)
+        .map_err(|e| format!("could not read {}: {}", path, e))
+        .and_then(|json| PlayerApp::from_json(&json))
+    {
+        Ok(app) => app,
+        Err(e) => exit(&e),
+    };
+
+    let native_options = eframe::NativeOptions {
+        initial_window_size: Some(egui::vec2(
+            app.cart.width as f32 * scale,
+            app.cart.height as f32 * scale,
+        )),
+        fullscreen,
+        ..Default::default()
+    };
+    eframe::run_native(
+        "fan-z player",
+        native_options,
+        Box::new(move |_cc| Box::new(app.fullscreen(fullscreen))),
+    );
+}
+
+fn exit(msg: &str) -> ! {
+    eprintln!("{}", msg);
+    std::process::exit(1)
+}
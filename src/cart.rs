@@ -0,0 +1,613 @@
+use array2d::Array2D;
+use egui::{Color32, Pos2};
+use std::collections::BTreeSet;
+
+/// loaded through `CartFile`, which is where missing fields get their defaults
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+#[serde(from = "CartFile")]
+pub struct Cart {
+    pub sprites: Vec<Sprite>,
+    /// the game starts in the first one
+    pub scenes: Vec<Scene>,
+    /// every scene's objects, see `EditorObject::scene`
+    pub objects: Vec<EditorObject>,
+    pub palette: Vec<Color32>,
+    /// size of the game screen in pixels
+    pub width: u32,
+    pub height: u32,
+    pub budget: Budget,
+    pub animations: Vec<Animation>,
+}
+/// how much work a single script call (one `draw`, or the top level of a script) may do before it's aborted
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug)]
+pub struct Budget {
+    /// zsp_core has no per instruction hook, so every call into a console builtin counts as one instruction
+    pub instructions: u64,
+    pub millis: u64,
+}
+impl Default for Budget {
+    fn default() -> Self {
+        Budget {
+            instructions: 100_000,
+            millis: 250,
+        }
+    }
+}
+pub const RESOLUTIONS: [(u32, u32); 4] = [(128, 128), (160, 120), (240, 136), (256, 144)];
+fn default_width() -> u32 {
+    160
+}
+fn default_height() -> u32 {
+    120
+}
+/// a cart as it's stored. carts from before scenes have a single `map`
+#[derive(serde::Deserialize)]
+struct CartFile {
+    sprites: Vec<Sprite>,
+    #[serde(default)]
+    map: Option<Array2D<Option<usize>>>,
+    #[serde(default)]
+    scenes: Vec<Scene>,
+    objects: Vec<EditorObject>,
+    #[serde(default = "default_palette")]
+    palette: Vec<Color32>,
+    #[serde(default = "default_width")]
+    width: u32,
+    #[serde(default = "default_height")]
+    height: u32,
+    #[serde(default)]
+    budget: Budget,
+    #[serde(default)]
+    animations: Vec<Animation>,
+}
+impl From<CartFile> for Cart {
+    fn from(file: CartFile) -> Self {
+        let mut scenes = file.scenes;
+        if scenes.is_empty() {
+            let mut scene = Scene::new("main".to_string());
+            if let Some(map) = file.map {
+                scene.map = map;
+            }
+            scenes.push(scene);
+        }
+        Cart {
+            sprites: file.sprites,
+            scenes,
+            objects: file.objects,
+            palette: file.palette,
+            width: file.width,
+            height: file.height,
+            budget: file.budget,
+            animations: file.animations,
+        }
+    }
+}
+impl Cart {
+    /// deletes a scene and its objects. the last scene can't go
+    pub fn removescene(&mut self, scene: usize) {
+        if self.scenes.len() <= 1 || scene >= self.scenes.len() {
+            return;
+        }
+        self.scenes.remove(scene);
+        let order: Vec<usize> = (0..self.objects.len())
+            .filter(|i| self.objects[*i].scene != scene)
+            .collect();
+        self.reorder(&order);
+        for obj in &mut self.objects {
+            if obj.scene > scene {
+                obj.scene -= 1;
+            }
+        }
+    }
+
+    /// the object's parent, if it has one in the same scene
+    pub fn parent(&self, object: usize) -> Option<usize> {
+        let obj = self.objects.get(object)?;
+        obj.parent
+            .filter(|p| *p != object && self.objects.get(*p).map(|o| o.scene) == Some(obj.scene))
+    }
+    /// where the object is in its scene, its own position plus all of its parents'
+    pub fn worldpos(&self, object: usize) -> Pos2 {
+        let mut pos = self.objects[object].pos;
+        let mut current = object;
+        // a broken cart could have parents going in circles
+        for _ in 0..self.objects.len() {
+            match self.parent(current) {
+                Some(p) => {
+                    pos += self.objects[p].pos.to_vec2();
+                    current = p;
+                }
+                None => break,
+            }
+        }
+        pos
+    }
+    /// the object and everything under it, the object first
+    pub fn subtree(&self, object: usize) -> Vec<usize> {
+        let mut out = vec![object];
+        let mut i = 0;
+        while i < out.len() {
+            for child in 0..self.objects.len() {
+                if self.parent(child) == Some(out[i]) && !out.contains(&child) {
+                    out.push(child);
+                }
+            }
+            i += 1;
+        }
+        out
+    }
+    /// rearranges the objects into `order`, a list of old indices. anything left out is deleted.
+    /// parents are fixed up, returns where every old index went
+    pub fn reorder(&mut self, order: &[usize]) -> Vec<Option<usize>> {
+        let mut moved = vec![None; self.objects.len()];
+        for (new, old) in order.iter().enumerate() {
+            moved[*old] = Some(new);
+        }
+        let mut old: Vec<Option<EditorObject>> = self.objects.drain(..).map(Some).collect();
+        self.objects = order.iter().filter_map(|i| old[*i].take()).collect();
+        for obj in &mut self.objects {
+            obj.parent = obj.parent.and_then(|p| moved.get(p).copied().flatten());
+        }
+        moved
+    }
+    /// deletes the object and its children
+    pub fn removeobject(&mut self, object: usize) -> Vec<Option<usize>> {
+        let gone = self.subtree(object);
+        let order: Vec<usize> = (0..self.objects.len())
+            .filter(|i| !gone.contains(i))
+            .collect();
+        self.reorder(&order)
+    }
+    /// moves the object in front of `before`, next to it in the tree
+    pub fn moveobject(&mut self, object: usize, before: usize) -> Vec<Option<usize>> {
+        if self.subtree(object).contains(&before) {
+            return (0..self.objects.len()).map(Some).collect();
+        }
+        self.objects[object].parent = self.parent(before);
+        let mut order: Vec<usize> = (0..self.objects.len()).filter(|i| *i != object).collect();
+        let at = order
+            .iter()
+            .position(|i| *i == before)
+            .unwrap_or(order.len());
+        order.insert(at, object);
+        self.reorder(&order)
+    }
+    /// makes the object the last child of `parent`, or the last object at the top of its scene.
+    /// it stays where it is in the scene. nothing happens when `parent` is under the object
+    pub fn reparent(&mut self, object: usize, parent: Option<usize>) -> Vec<Option<usize>> {
+        if parent.map_or(false, |p| self.subtree(object).contains(&p)) {
+            return (0..self.objects.len()).map(Some).collect();
+        }
+        let parentpos = parent.map_or(Pos2::ZERO, |p| self.worldpos(p));
+        self.objects[object].pos = (self.worldpos(object) - parentpos).to_pos2();
+        self.objects[object].parent = parent;
+        let mut order: Vec<usize> = (0..self.objects.len()).filter(|i| *i != object).collect();
+        order.push(object);
+        self.reorder(&order)
+    }
+    /// copies the object, without its children, right after it. returns the copy's index
+    pub fn duplicate(&mut self, object: usize) -> usize {
+        let mut copy = self.objects[object].clone();
+        copy.name = format!("{} copy", copy.name);
+        self.objects.push(copy);
+        let mut order: Vec<usize> = (0..self.objects.len() - 1).collect();
+        order.insert(object + 1, self.objects.len() - 1);
+        self.reorder(&order);
+        object + 1
+    }
+}
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct Scene {
+    pub name: String,
+    pub map: Array2D<Option<usize>>,
+}
+impl Scene {
+    pub fn new(name: String) -> Scene {
+        Scene {
+            name,
+            map: Array2D::filled_with(None, 8, 8),
+        }
+    }
+}
+impl Default for Cart {
+    fn default() -> Self {
+        Cart {
+            scenes: vec![Scene::new("main".to_string())],
+            sprites: vec![],
+            objects: vec![],
+            palette: default_palette(),
+            width: default_width(),
+            height: default_height(),
+            budget: Budget::default(),
+            animations: vec![],
+        }
+    }
+}
+pub const PICO8_PALETTE: [Color32; 16] = [
+    Color32::from_rgb(0x00, 0x00, 0x00),
+    Color32::from_rgb(0x1d, 0x2b, 0x53),
+    Color32::from_rgb(0x7e, 0x25, 0x53),
+    Color32::from_rgb(0x00, 0x87, 0x51),
+    Color32::from_rgb(0xab, 0x52, 0x36),
+    Color32::from_rgb(0x5f, 0x57, 0x4f),
+    Color32::from_rgb(0xc2, 0xc3, 0xc7),
+    Color32::from_rgb(0xff, 0xf1, 0xe8),
+    Color32::from_rgb(0xff, 0x00, 0x4d),
+    Color32::from_rgb(0xff, 0xa3, 0x00),
+    Color32::from_rgb(0xff, 0xec, 0x27),
+    Color32::from_rgb(0x00, 0xe4, 0x36),
+    Color32::from_rgb(0x29, 0xad, 0xff),
+    Color32::from_rgb(0x83, 0x76, 0x9c),
+    Color32::from_rgb(0xff, 0x77, 0xa8),
+    Color32::from_rgb(0xff, 0xcc, 0xaa),
+];
+pub fn default_palette() -> Vec<Color32> {
+    PICO8_PALETTE.to_vec()
+}
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct EditorObject {
+    pub pos: Pos2,
+    pub name: String,
+    pub script: String,
+    /// index into `Cart::scenes`, the object only runs in that scene unless it's spawned
+    #[serde(default)]
+    pub scene: usize,
+    /// index into `Cart::objects`. `pos` is relative to the parent's position, see `Cart::worldpos`
+    #[serde(default)]
+    pub parent: Option<usize>,
+    /// lines the debugger stops on, 1 based
+    #[serde(default)]
+    pub breakpoints: BTreeSet<usize>,
+    /// drawn at `pos` every frame, before the script's `draw`
+    #[serde(default)]
+    pub sprite: Option<usize>,
+    /// what the object draws goes on this layer, see `LAYERS`. scripts can change it with `layer`
+    #[serde(default = "default_drawlayer")]
+    pub drawlayer: usize,
+    /// objects with a lower depth run and draw first, so on the same layer higher ones end up on top
+    #[serde(default)]
+    pub depth: i32,
+    /// disabled objects don't run at all
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// doesn't run at the start, only copies made with `spawn` do
+    #[serde(default)]
+    pub prototype: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// variables set in the script's root scope before it starts
+    #[serde(default)]
+    pub properties: Vec<Property>,
+}
+fn default_enabled() -> bool {
+    true
+}
+/// draw layers, back to front
+pub const LAYERS: [&str; 4] = ["background", "map", "actors", "ui"];
+pub const MAPLAYER: usize = 1;
+pub const ACTORLAYER: usize = 2;
+fn default_drawlayer() -> usize {
+    ACTORLAYER
+}
+/// sprites are this many pixels on each side, and so are map tiles
+pub const TILESIZE: u32 = 8;
+impl EditorObject {
+    pub fn new(name: String, pos: Pos2, script: String) -> EditorObject {
+        EditorObject {
+            pos,
+            name,
+            script,
+            scene: 0,
+            parent: None,
+            breakpoints: BTreeSet::new(),
+            sprite: None,
+            drawlayer: ACTORLAYER,
+            depth: 0,
+            enabled: true,
+            prototype: false,
+            tags: vec![],
+            properties: vec![],
+        }
+    }
+}
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub struct Property {
+    pub name: String,
+    pub value: PropertyValue,
+}
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub enum PropertyValue {
+    Number(f32),
+    String(String),
+    /// shows up in the script as a `color`
+    Color(Color32),
+    /// shows up in the script as the sprite's index
+    Sprite(usize),
+}
+
+/// what the sprite flags are for. scripts can use the names with `fget` and `fset`
+pub const FLAGNAMES: [&str; 8] = [
+    "solid", "hazard", "ladder", "flag3", "flag4", "flag5", "flag6", "flag7",
+];
+/// how the flags show up in the editor, same colors as pico-8
+pub const FLAGCOLORS: [Color32; 8] = [
+    Color32::from_rgb(0xff, 0x00, 0x4d),
+    Color32::from_rgb(0xff, 0xa3, 0x00),
+    Color32::from_rgb(0xff, 0xec, 0x27),
+    Color32::from_rgb(0x00, 0xe4, 0x36),
+    Color32::from_rgb(0x29, 0xad, 0xff),
+    Color32::from_rgb(0x83, 0x76, 0x9c),
+    Color32::from_rgb(0xff, 0x77, 0xa8),
+    Color32::from_rgb(0xff, 0xcc, 0xaa),
+];
+/// the flag `solid` looks at on map tiles
+pub const SOLIDFLAG: usize = 0;
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct Sprite {
+    pub data: Array2D<Color32>,
+    /// 8 general purpose flags, bit n is flag n
+    #[serde(default)]
+    pub flags: u8,
+}
+impl Sprite {
+    pub fn new() -> Sprite {
+        Sprite {
+            data: Array2D::filled_with(Color32::TRANSPARENT, 8, 8),
+            flags: 0,
+        }
+    }
+    pub fn flag(&self, flag: usize) -> bool {
+        flag < 8 && self.flags & (1 << flag) != 0
+    }
+    pub fn setflag(&mut self, flag: usize, on: bool) {
+        if flag >= 8 {
+            return;
+        }
+        if on {
+            self.flags |= 1 << flag;
+        } else {
+            self.flags &= !(1 << flag);
+        }
+    }
+}
+
+/// a named run of sprites, played by `anim`
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+pub struct Animation {
+    pub name: String,
+    pub frames: Vec<AnimationFrame>,
+    pub mode: LoopMode,
+}
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug)]
+pub struct AnimationFrame {
+    pub sprite: usize,
+    /// how long the sprite shows, in game frames
+    pub duration: u32,
+}
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoopMode {
+    Loop,
+    /// stops on the last frame
+    Once,
+    /// plays forwards, then backwards
+    PingPong,
+}
+impl Animation {
+    pub fn new(name: String) -> Animation {
+        Animation {
+            name,
+            frames: vec![],
+            mode: LoopMode::Loop,
+        }
+    }
+    /// game frames one pass takes
+    pub fn length(&self) -> u64 {
+        self.frames.iter().map(|f| f.duration as u64).sum()
+    }
+    /// the sprite showing `time` game frames after the animation started
+    pub fn sprite_at(&self, time: u64) -> Option<usize> {
+        let length = self.length();
+        if length == 0 {
+            return self.frames.first().map(|f| f.sprite);
+        }
+        let t = match self.mode {
+            LoopMode::Loop => time % length,
+            LoopMode::Once => time.min(length - 1),
+            LoopMode::PingPong => match time % (length * 2) {
+                t if t < length => t,
+                t => length * 2 - 1 - t,
+            },
+        };
+        let mut end = 0;
+        for frame in &self.frames {
+            end += frame.duration as u64;
+            if t < end {
+                return Some(frame.sprite);
+            }
+        }
+        self.frames.last().map(|f| f.sprite)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::pos2;
+
+    /// a, b under a, c under b, d
+    fn tree() -> Cart {
+        let mut cart = Cart::default();
+        for (name, x) in [("a", 1.0), ("b", 10.0), ("c", 100.0), ("d", 1000.0)] {
+            cart.objects
+                .push(EditorObject::new(name.into(), pos2(x, 0.0), String::new()));
+        }
+        cart.objects[1].parent = Some(0);
+        cart.objects[2].parent = Some(1);
+        cart
+    }
+    fn names(cart: &Cart) -> Vec<&str> {
+        cart.objects.iter().map(|o| o.name.as_str()).collect()
+    }
+
+    #[test]
+    fn old_carts_get_defaults_and_a_scene() {
+        let map = serde_json::to_value(Array2D::filled_with(Some(3usize), 2, 2)).unwrap();
+        let json = serde_json::json!({ "sprites": [], "objects": [], "map": map });
+        let cart: Cart = serde_json::from_value(json).unwrap();
+        assert_eq!((cart.width, cart.height), (160, 120));
+        assert_eq!(cart.palette, default_palette());
+        assert_eq!(cart.budget.instructions, Budget::default().instructions);
+        assert_eq!(cart.scenes.len(), 1);
+        assert_eq!(cart.scenes[0].map.get(1, 1), Some(&Some(3)));
+    }
+
+    #[test]
+    fn worldpos_and_subtree_follow_parents() {
+        let cart = tree();
+        assert_eq!(cart.worldpos(2), pos2(111.0, 0.0));
+        assert_eq!(cart.subtree(0), vec![0, 1, 2]);
+        assert_eq!(cart.subtree(3), vec![3]);
+    }
+
+    #[test]
+    fn reorder_fixes_parents() {
+        let mut cart = tree();
+        let moved = cart.reorder(&[3, 2, 1, 0]);
+        assert_eq!(names(&cart), ["d", "c", "b", "a"]);
+        assert_eq!(moved, vec![Some(3), Some(2), Some(1), Some(0)]);
+        assert_eq!(cart.objects[1].parent, Some(2));
+        assert_eq!(cart.objects[2].parent, Some(3));
+        assert_eq!(cart.worldpos(1), pos2(111.0, 0.0));
+    }
+
+    #[test]
+    fn reorder_drops_what_is_left_out() {
+        let mut cart = tree();
+        let moved = cart.reorder(&[0, 2, 3]);
+        assert_eq!(names(&cart), ["a", "c", "d"]);
+        assert_eq!(moved, vec![Some(0), None, Some(1), Some(2)]);
+        // its parent is gone
+        assert_eq!(cart.objects[1].parent, None);
+    }
+
+    #[test]
+    fn removeobject_takes_the_children_along() {
+        let mut cart = tree();
+        cart.removeobject(1);
+        assert_eq!(names(&cart), ["a", "d"]);
+    }
+
+    #[test]
+    fn moveobject_takes_the_parent_of_where_it_goes() {
+        let mut cart = tree();
+        cart.moveobject(3, 1);
+        assert_eq!(names(&cart), ["a", "d", "b", "c"]);
+        assert_eq!(cart.objects[1].parent, Some(0));
+        assert_eq!(cart.objects[2].parent, Some(0));
+        assert_eq!(cart.objects[3].parent, Some(2));
+
+        // moving to the top of the scene leaves the tree
+        cart.moveobject(3, 0);
+        assert_eq!(names(&cart), ["c", "a", "d", "b"]);
+        assert_eq!(cart.objects[0].parent, None);
+    }
+
+    #[test]
+    fn moveobject_refuses_to_go_under_itself() {
+        let mut cart = tree();
+        let moved = cart.moveobject(0, 2);
+        assert_eq!(names(&cart), ["a", "b", "c", "d"]);
+        assert_eq!(moved, vec![Some(0), Some(1), Some(2), Some(3)]);
+        assert_eq!(cart.objects[0].parent, None);
+    }
+
+    #[test]
+    fn reparent_goes_last_and_stays_put() {
+        let mut cart = tree();
+        // c under d, from under b
+        let moved = cart.reparent(2, Some(3));
+        assert_eq!(names(&cart), ["a", "b", "d", "c"]);
+        assert_eq!(moved, vec![Some(0), Some(1), Some(3), Some(2)]);
+        assert_eq!(cart.parent(3), Some(2));
+        assert_eq!(cart.worldpos(3), pos2(111.0, 0.0));
+        // and back to the top
+        cart.reparent(3, None);
+        assert_eq!(cart.parent(3), None);
+        assert_eq!(cart.objects[3].pos, pos2(111.0, 0.0));
+    }
+
+    #[test]
+    fn reparent_refuses_to_go_under_itself() {
+        let mut cart = tree();
+        let moved = cart.reparent(0, Some(2));
+        assert_eq!(names(&cart), ["a", "b", "c", "d"]);
+        assert_eq!(moved, vec![Some(0), Some(1), Some(2), Some(3)]);
+        assert_eq!(cart.objects[0].parent, None);
+    }
+
+    #[test]
+    fn duplicate_goes_right_after() {
+        let mut cart = tree();
+        assert_eq!(cart.duplicate(1), 2);
+        assert_eq!(names(&cart), ["a", "b", "b copy", "c", "d"]);
+        assert_eq!(cart.objects[2].parent, Some(0));
+        // c stays under the original
+        assert_eq!(cart.objects[3].parent, Some(1));
+    }
+
+    fn walk() -> Animation {
+        let mut anim = Animation::new("walk".into());
+        anim.frames = vec![
+            AnimationFrame {
+                sprite: 5,
+                duration: 2,
+            },
+            AnimationFrame {
+                sprite: 6,
+                duration: 1,
+            },
+            AnimationFrame {
+                sprite: 7,
+                duration: 3,
+            },
+        ];
+        anim
+    }
+    fn play(anim: &Animation, frames: u64) -> Vec<usize> {
+        (0..frames).map(|t| anim.sprite_at(t).unwrap()).collect()
+    }
+
+    #[test]
+    fn sprite_at_loops() {
+        let anim = walk();
+        assert_eq!(anim.length(), 6);
+        assert_eq!(play(&anim, 8), [5, 5, 6, 7, 7, 7, 5, 5]);
+    }
+
+    #[test]
+    fn sprite_at_once_stops_on_the_last_frame() {
+        let mut anim = walk();
+        anim.mode = LoopMode::Once;
+        assert_eq!(play(&anim, 8), [5, 5, 6, 7, 7, 7, 7, 7]);
+        assert_eq!(anim.sprite_at(1_000_000), Some(7));
+    }
+
+    #[test]
+    fn sprite_at_pingpong_plays_back() {
+        let mut anim = walk();
+        anim.mode = LoopMode::PingPong;
+        assert_eq!(play(&anim, 14), [5, 5, 6, 7, 7, 7, 7, 7, 7, 6, 5, 5, 5, 5]);
+    }
+
+    #[test]
+    fn sprite_at_without_time() {
+        let mut anim = Animation::new("empty".into());
+        assert_eq!(anim.sprite_at(3), None);
+        // zero length frames still show something
+        anim.frames.push(AnimationFrame {
+            sprite: 2,
+            duration: 0,
+        });
+        assert_eq!(anim.sprite_at(3), Some(2));
+    }
+}
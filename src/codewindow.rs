@@ -1,4 +1,3 @@
-use crate::zvm::{self, ZEvent, ZVMState, STATE_PTR, ZVM};
 use crate::FanzApp;
 use egui::{
     pos2, Align2, Color32, Id, Key, LayerId, Painter, Rect, RichText, Sense, Stroke, Vec2, Widget,
@@ -13,18 +12,86 @@ impl Default for CodeWindow {
         CodeWindow { enabled: false }
     }
 }
+/// room left of the text for line numbers and breakpoints
+const GUTTER: f32 = 32.0;
 impl CodeWindow {
     pub fn ui<'a>(&mut self, app: &mut FanzApp<'a>, ui: &mut egui::Ui) {
+        let stopped = app
+            .gamewindow
+            .borrow()
+            .brk
+            .as_ref()
+            .map(|b| (b.object, b.line));
+        let selected = app.selectedobject;
         egui::ScrollArea::vertical().show(ui, |ui| {
-            if let Some(obj) = app.cart.objects.get_mut(app.selectedobject) {
-                ui.add(
-                    egui::TextEdit::multiline(&mut obj.script)
-                        .font(egui::TextStyle::Monospace) // for cursor height
-                        .code_editor()
-                        .desired_rows(40)
-                        .lock_focus(true)
-                        .desired_width(f32::INFINITY),
-                );
+            if let Some(obj) = app.cart.objects.get_mut(selected) {
+                let output = egui::TextEdit::multiline(&mut obj.script)
+                    .font(egui::TextStyle::Monospace) // for cursor height
+                    .code_editor()
+                    .desired_rows(40)
+                    .lock_focus(true)
+                    .desired_width(f32::INFINITY)
+                    .margin(Vec2::new(GUTTER, 2.0))
+                    .show(ui);
+
+                // screen rect and line number of every row, wrapped rows don't get a number
+                let mut rows = vec![];
+                let mut line = 1;
+                let mut startsline = true;
+                for row in &output.galley.rows {
+                    let rect = row.rect.translate(output.text_draw_pos.to_vec2());
+                    rows.push((rect, if startsline { Some(line) } else { None }));
+                    startsline = row.ends_with_newline;
+                    if row.ends_with_newline {
+                        line += 1;
+                    }
+                }
+
+                let resp = &output.response;
+                if resp.clicked() {
+                    if let Some(pos) = resp.interact_pointer_pos() {
+                        let hit = rows.iter().find(|(r, _)| r.y_range().contains(&pos.y));
+                        if let (true, Some((_, Some(line)))) = (pos.x < output.text_draw_pos.x, hit)
+                        {
+                            if !obj.breakpoints.remove(line) {
+                                obj.breakpoints.insert(*line);
+                            }
+                        }
+                    }
+                }
+
+                let painter = ui.painter_at(resp.rect);
+                for (rect, line) in rows {
+                    let line = match line {
+                        Some(l) => l,
+                        None => continue,
+                    };
+                    let gutter = Rect::from_min_max(
+                        pos2(resp.rect.min.x, rect.min.y),
+                        pos2(output.text_draw_pos.x, rect.max.y),
+                    );
+                    if stopped == Some((selected, line)) {
+                        painter.rect_filled(
+                            Rect::from_min_max(gutter.min, pos2(resp.rect.max.x, gutter.max.y)),
+                            0.0,
+                            Color32::from_rgba_unmultiplied(255, 200, 0, 40),
+                        );
+                    }
+                    if obj.breakpoints.contains(&line) {
+                        painter.circle_filled(
+                            pos2(gutter.min.x + 6.0, gutter.center().y),
+                            4.0,
+                            Color32::RED,
+                        );
+                    }
+                    painter.text(
+                        pos2(gutter.max.x - 4.0, gutter.center().y),
+                        Align2::RIGHT_CENTER,
+                        line.to_string(),
+                        egui::FontId::monospace(10.0),
+                        Color32::GRAY,
+                    );
+                }
             }
         });
     }
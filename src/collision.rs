@@ -0,0 +1,180 @@
+use crate::cart::{Sprite, SOLIDFLAG, TILESIZE};
+use array2d::Array2D;
+use egui::{vec2, Pos2, Rect};
+
+/// where a running object is, for collision checks. refreshed by the runner every frame
+#[derive(Debug, Clone, Copy)]
+pub struct Body {
+    pub id: u64,
+    /// which `EditorObject` it came from
+    pub index: usize,
+    pub pos: Pos2,
+    pub sprite: Option<usize>,
+}
+impl Body {
+    /// the object's sprite at its position, or a tile sized box when it has none
+    pub fn rect(&self, sprites: &[Sprite]) -> Rect {
+        let size = match self.sprite.and_then(|s| sprites.get(s)) {
+            Some(s) => vec2(s.data.num_rows() as f32, s.data.num_columns() as f32),
+            None => vec2(TILESIZE as f32, TILESIZE as f32),
+        };
+        Rect::from_min_size(self.pos.floor(), size)
+    }
+}
+
+/// boxes touching only at an edge don't overlap
+pub fn overlap(a: Rect, b: Rect) -> bool {
+    a.min.x < b.max.x && b.min.x < a.max.x && a.min.y < b.max.y && b.min.y < a.max.y
+}
+pub fn inrect(p: Pos2, r: Rect) -> bool {
+    p.x >= r.min.x && p.x < r.max.x && p.y >= r.min.y && p.y < r.max.y
+}
+
+/// the boxes overlap and somewhere in there both sprites have a pixel that isn't fully transparent.
+/// an object without a sprite counts as solid all over
+pub fn pixeloverlap(a: &Body, b: &Body, sprites: &[Sprite]) -> bool {
+    let (ra, rb) = (a.rect(sprites), b.rect(sprites));
+    if !overlap(ra, rb) {
+        return false;
+    }
+    let both = ra.intersect(rb);
+    let opaque =
+        |body: &Body, rect: Rect, x: f32, y: f32| match body.sprite.and_then(|s| sprites.get(s)) {
+            Some(s) => s
+                .data
+                .get((x - rect.min.x) as usize, (y - rect.min.y) as usize)
+                .map_or(false, |c| c.a() > 0),
+            None => true,
+        };
+    let mut y = both.min.y;
+    while y < both.max.y {
+        let mut x = both.min.x;
+        while x < both.max.x {
+            if opaque(a, ra, x, y) && opaque(b, rb, x, y) {
+                return true;
+            }
+            x += 1.0;
+        }
+        y += 1.0;
+    }
+    false
+}
+
+/// the map tile under a pixel has a sprite with the solid flag set. off the map is open
+pub fn solid(map: &Array2D<Option<usize>>, sprites: &[Sprite], x: f32, y: f32) -> bool {
+    if x < 0.0 || y < 0.0 {
+        return false;
+    }
+    let (tx, ty) = (
+        x as usize / TILESIZE as usize,
+        y as usize / TILESIZE as usize,
+    );
+    match map
+        .get(tx, ty)
+        .copied()
+        .flatten()
+        .and_then(|s| sprites.get(s))
+    {
+        Some(sprite) => sprite.flag(SOLIDFLAG),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::{pos2, Color32};
+
+    fn rect(x: f32, y: f32, w: f32, h: f32) -> Rect {
+        Rect::from_min_size(pos2(x, y), vec2(w, h))
+    }
+    fn body(id: u64, x: f32, y: f32, sprite: Option<usize>) -> Body {
+        Body {
+            id,
+            index: 0,
+            pos: pos2(x, y),
+            sprite,
+        }
+    }
+    /// sprite 0 is transparent but for its top left pixel, sprite 1 is solid but for its
+    /// top left pixel, sprite 2 has the solid flag
+    fn sprites() -> Vec<Sprite> {
+        let mut corner = Sprite::new();
+        *corner.data.get_mut(0, 0).unwrap() = Color32::RED;
+        let mut rest = Sprite {
+            data: Array2D::filled_with(Color32::RED, 8, 8),
+            flags: 0,
+        };
+        *rest.data.get_mut(0, 0).unwrap() = Color32::TRANSPARENT;
+        let mut wall = Sprite::new();
+        wall.setflag(SOLIDFLAG, true);
+        vec![corner, rest, wall]
+    }
+
+    #[test]
+    fn overlap_needs_more_than_an_edge() {
+        let a = rect(0.0, 0.0, 8.0, 8.0);
+        assert!(overlap(a, rect(7.0, 7.0, 8.0, 8.0)));
+        assert!(!overlap(a, rect(8.0, 0.0, 8.0, 8.0)));
+        assert!(!overlap(a, rect(0.0, 8.0, 8.0, 8.0)));
+        assert!(!overlap(a, rect(8.0, 8.0, 8.0, 8.0)));
+        assert!(overlap(a, rect(2.0, 2.0, 1.0, 1.0)));
+    }
+
+    #[test]
+    fn inrect_includes_the_top_left_edge_only() {
+        let r = rect(0.0, 0.0, 8.0, 8.0);
+        assert!(inrect(pos2(0.0, 0.0), r));
+        assert!(inrect(pos2(7.9, 7.9), r));
+        assert!(!inrect(pos2(8.0, 4.0), r));
+        assert!(!inrect(pos2(4.0, 8.0), r));
+        assert!(!inrect(pos2(-0.1, 4.0), r));
+    }
+
+    #[test]
+    fn pixeloverlap_skips_transparent_pixels() {
+        let sprites = sprites();
+        // the corner pixel of one lands on the hole of the other
+        assert!(!pixeloverlap(
+            &body(1, 0.0, 0.0, Some(0)),
+            &body(2, 0.0, 0.0, Some(1)),
+            &sprites
+        ));
+        // one pixel over, the corner hits red
+        assert!(pixeloverlap(
+            &body(1, 1.0, 0.0, Some(0)),
+            &body(2, 0.0, 0.0, Some(1)),
+            &sprites
+        ));
+        // no sprite is solid all over
+        assert!(pixeloverlap(
+            &body(1, 4.0, 4.0, None),
+            &body(2, 0.0, 0.0, Some(1)),
+            &sprites
+        ));
+        // touching edges isn't enough, whatever the pixels are
+        assert!(!pixeloverlap(
+            &body(1, 8.0, 0.0, None),
+            &body(2, 0.0, 0.0, None),
+            &sprites
+        ));
+    }
+
+    #[test]
+    fn solid_reads_the_flag_and_is_open_off_the_map() {
+        let sprites = sprites();
+        let mut map = Array2D::filled_with(None, 2, 2);
+        *map.get_mut(1, 0).unwrap() = Some(2);
+        *map.get_mut(0, 1).unwrap() = Some(1);
+        // sprites that don't exist aren't solid either
+        *map.get_mut(1, 1).unwrap() = Some(9);
+        assert!(solid(&map, &sprites, 8.0, 0.0));
+        assert!(solid(&map, &sprites, 15.9, 7.9));
+        assert!(!solid(&map, &sprites, 7.9, 0.0));
+        assert!(!solid(&map, &sprites, 0.0, 8.0));
+        assert!(!solid(&map, &sprites, 8.0, 8.0));
+        assert!(!solid(&map, &sprites, -1.0, 0.0));
+        assert!(!solid(&map, &sprites, 16.0, 0.0));
+        assert!(!solid(&map, &sprites, 0.0, 100.0));
+    }
+}
@@ -0,0 +1,488 @@
+use crate::runner::GameCommand;
+use crate::zvm;
+use instant::Instant;
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+use zsp_core::{
+    exceptions::Exception,
+    runtime::{Scope, Value},
+};
+
+// zsp_core doesn't have any hooks for stepping through a script, so a debug run puts a call to
+// this builtin in front of every line that can take one. the builtin is where the game stops
+pub const BREAKPOINT_FN: &str = "__bp";
+pub const DEBUG_STOP_EXCEPTION: &str = "DebuggerStoppedException";
+
+/// what the ui answers when the game is stopped at a line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugCommand {
+    Continue,
+    /// next line in this call, or in whatever called it
+    StepOver,
+    /// next line anywhere, including inside functions called from here
+    StepInto,
+    /// the game is going away, unwind the script
+    Stop,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepMode {
+    Run,
+    Into,
+    /// `depth` is how deep the native stack was on the line we stepped from
+    Over {
+        object: usize,
+        depth: usize,
+    },
+}
+/// where the game stopped, sent to the ui
+#[derive(Debug, Clone)]
+pub struct BreakInfo {
+    pub object: usize,
+    pub objectname: String,
+    /// 1 based
+    pub line: usize,
+    /// innermost first
+    pub stack: Vec<String>,
+    /// top level variables of the object's script, name and value
+    pub variables: Vec<(String, String)>,
+    /// stopped in the top level of the script, where the root scope is also the current one
+    pub toplevel: bool,
+}
+/// sends the break to the ui and waits for its answer. None when the ui hung up
+pub type BreakHook = Box<dyn FnMut(&BreakInfo) -> Option<GameCommand>>;
+
+pub struct Debugger {
+    /// breakpoint lines per object, in cart order
+    pub breakpoints: Vec<BTreeSet<usize>>,
+    pub mode: StepMode,
+    /// None when the game can't stop, like on the web where there's no thread to block
+    pub hook: Option<BreakHook>,
+
+    // what's running right now, set by the runner before every call into a script
+    pub object: usize,
+    pub objectname: String,
+    pub entry: &'static str,
+    /// root scope of the running script, see `show`
+    pub scope: Option<Rc<RefCell<Scope<'static>>>>,
+    /// its variables when the call started. a running call has the scope borrowed, but the
+    /// variables themselves are shared with it, so these stay current
+    pub variables: Vec<(String, Rc<RefCell<Value<'static>>>)>,
+    /// what the ui sent while the game was stopped that wasn't for the debugger,
+    /// for the runner to pick up once it goes on
+    pub queued: Vec<GameCommand>,
+    /// native stack depth and line of the last line hit in every call that's still running
+    pub stack: Vec<(usize, usize)>,
+    /// where the last line hit starts in the running script, `zvm::NOWHERE` before the first
+    pub at: usize,
+}
+impl std::fmt::Debug for Debugger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Debugger")
+            .field("breakpoints", &self.breakpoints)
+            .field("mode", &self.mode)
+            .field("object", &self.object)
+            .field("entry", &self.entry)
+            .finish()
+    }
+}
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: vec![],
+            mode: StepMode::Run,
+            hook: None,
+            object: 0,
+            objectname: String::new(),
+            entry: "",
+            scope: None,
+            variables: vec![],
+            queued: vec![],
+            stack: vec![],
+            at: zvm::NOWHERE,
+        }
+    }
+    /// call before handing control to an object's script
+    pub fn enter(&mut self, object: usize, name: &str, entry: &'static str) {
+        self.object = object;
+        self.objectname = name.to_string();
+        self.entry = entry;
+        self.stack.clear();
+        self.at = zvm::NOWHERE;
+        self.scope = None;
+        self.variables.clear();
+    }
+    /// lets breakpoints show the variables of `scope`, only while debugging since it costs a copy
+    pub fn show(&mut self, scope: &Rc<RefCell<Scope<'static>>>) {
+        if self.hook.is_none() {
+            return;
+        }
+        self.scope = Some(scope.clone());
+        self.variables = match scope.try_borrow() {
+            Ok(s) => s
+                .variables
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            Err(_) => vec![],
+        };
+    }
+    fn info(&self, line: usize) -> BreakInfo {
+        let mut stack: Vec<String> = self
+            .stack
+            .iter()
+            .rev()
+            .map(|(_, l)| format!("line {}", l))
+            .collect();
+        stack.push(format!("{} ({})", self.entry, self.objectname));
+        BreakInfo {
+            object: self.object,
+            objectname: self.objectname.clone(),
+            line,
+            stack,
+            variables: self.variables(),
+            toplevel: self.entry == TOPLEVEL,
+        }
+    }
+    fn variables(&self) -> Vec<(String, String)> {
+        // the scope itself when nothing holds it, so what was defined since the call started shows
+        // up too. zsp_core has no way to get at a function's own scope, so only the root one shows
+        let live = self.scope.as_ref().and_then(|s| s.try_borrow().ok());
+        let variables = match &live {
+            Some(scope) => scope.variables.iter().collect::<Vec<_>>(),
+            None => self.variables.iter().map(|(n, v)| (n, v)).collect(),
+        };
+        let mut vars: Vec<(String, String)> = variables
+            .into_iter()
+            .map(|(name, value)| {
+                let value = match value.try_borrow() {
+                    Ok(v) => v.to_string(),
+                    Err(_) => "<in use>".to_string(),
+                };
+                (name.clone(), value)
+            })
+            .collect();
+        vars.sort();
+        vars
+    }
+}
+pub const TOPLEVEL: &str = "top level";
+pub const DRAW: &str = "draw";
+pub const MESSAGE: &str = "on_message";
+
+/// called by the breakpoint builtin at the start of every instrumented line,
+/// `at` is where the call is in the running script
+pub fn hit(line: usize, at: usize) -> Result<(), Exception> {
+    // the interpreter walks the tree recursively, so how deep the native stack is tells
+    // which zsp calls are still running
+    let marker = 0u8;
+    let depth = &marker as *const u8 as usize;
+
+    let stop = zvm::with_state(|state| {
+        let debug = &mut state.debug;
+        while let Some(&(d, _)) = debug.stack.last() {
+            if d > depth {
+                break;
+            }
+            debug.stack.pop();
+        }
+        debug.stack.push((depth, line));
+        debug.at = at;
+
+        let breakpoint = debug
+            .breakpoints
+            .get(debug.object)
+            .map_or(false, |b| b.contains(&line));
+        let stepped = match debug.mode {
+            StepMode::Run => false,
+            StepMode::Into => true,
+            StepMode::Over { object, depth: d } => object == debug.object && depth >= d,
+        };
+        if !breakpoint && !stepped {
+            return None;
+        }
+        let hook = debug.hook.take()?;
+        Some((hook, debug.info(line), state.callstart.elapsed()))
+    });
+    let (mut hook, info, elapsed) = match stop {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+    // the state is free while we wait, the hook doesn't need it
+    let mut breakpoints = None;
+    let mut queued = vec![];
+    let cmd = loop {
+        match hook(&info) {
+            Some(GameCommand::Debug(cmd)) => break cmd,
+            Some(GameCommand::Resume) => break DebugCommand::Continue,
+            Some(GameCommand::Breakpoints(b)) => breakpoints = Some(b),
+            // only the latest input matters, and it keeps coming while we wait
+            Some(input @ GameCommand::Input { .. }) => {
+                queued.retain(|c| !matches!(c, GameCommand::Input { .. }));
+                queued.push(input);
+            }
+            Some(cmd) => queued.push(cmd),
+            None => break DebugCommand::Stop,
+        }
+    };
+    zvm::with_state(|state| {
+        state.debug.hook = Some(hook);
+        state.debug.queued.extend(queued);
+        if let Some(b) = breakpoints {
+            state.debug.breakpoints = b;
+        }
+        // time spent sitting at the breakpoint doesn't count against the budget
+        state.callstart = Instant::now() - elapsed;
+        state.debug.mode = match cmd {
+            DebugCommand::Continue | DebugCommand::Stop => StepMode::Run,
+            DebugCommand::StepInto => StepMode::Into,
+            DebugCommand::StepOver => StepMode::Over {
+                object: state.debug.object,
+                depth,
+            },
+        };
+    });
+    if cmd == DebugCommand::Stop {
+        return Err(Exception::new(
+            at,
+            DEBUG_STOP_EXCEPTION,
+            "the game was stopped at a breakpoint",
+        ));
+    }
+    Ok(())
+}
+
+/// puts a breakpoint call in front of every line that looks like the start of a statement.
+/// the call gets the line and where it is in the new script, so builtins can say where they failed.
+/// returns the new script and where the calls went (char index, length), see `unmap`
+pub fn instrument(source: &str) -> (String, Vec<(usize, usize)>) {
+    let mut out = String::new();
+    let mut inserted = vec![];
+    let mut chars = 0;
+    let mut open = Open::default();
+    for (i, line) in source.split('\n').enumerate() {
+        if i > 0 {
+            out.push('\n');
+            chars += 1;
+        }
+        if breakable(&open, line.trim()) {
+            let indent = &line[..line.len() - line.trim_start().len()];
+            let call = format!("{}{}({}, {})\n", indent, BREAKPOINT_FN, i + 1, chars);
+            let len = call.chars().count();
+            inserted.push((chars, len));
+            chars += len;
+            out.push_str(&call);
+        }
+        out.push_str(line);
+        chars += line.chars().count();
+        open.scan(line);
+    }
+    (out, inserted)
+}
+/// what's still open after the lines `instrument` went through so far
+#[derive(Default)]
+struct Open {
+    /// in a string that goes on past the end of the line
+    string: bool,
+    /// brackets that weren't closed yet, true for blocks that hold statements
+    brackets: Vec<bool>,
+    /// last char that isn't whitespace or a comment
+    last: Option<char>,
+}
+impl Open {
+    fn scan(&mut self, line: &str) {
+        let mut escaped = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            if self.string {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => self.string = false,
+                    _ => (),
+                }
+                self.last = Some(c);
+                continue;
+            }
+            match c {
+                '"' => self.string = true,
+                '/' if chars.peek() == Some(&'/') => break,
+                // `{` after one of these starts a value, like `p = { x: 1 }`, not a block
+                '{' => self
+                    .brackets
+                    .push(!matches!(self.last, Some('=' | '(' | '[' | ',' | ':'))),
+                '(' | '[' => self.brackets.push(false),
+                '}' | ')' | ']' => {
+                    self.brackets.pop();
+                }
+                _ => (),
+            }
+            if !c.is_whitespace() {
+                self.last = Some(c);
+            }
+        }
+    }
+}
+/// a line can take a breakpoint unless it's empty, a comment, or continues what came before
+fn breakable(open: &Open, line: &str) -> bool {
+    if open.string || open.brackets.last() == Some(&false) {
+        return false;
+    }
+    if line.is_empty() || line.starts_with("//") || line.starts_with("else") {
+        return false;
+    }
+    if line.starts_with(|c| "})],.+-*/%&|<>=!?:".contains(c)) {
+        return false;
+    }
+    !open.last.map_or(false, |c| "([,=+-*/%&|<>!.".contains(c))
+}
+/// maps a char index in an instrumented script back to the original
+pub fn unmap(idx: usize, inserted: &[(usize, usize)]) -> usize {
+    let mut shift = 0;
+    for &(start, len) in inserted {
+        if idx < start {
+            break;
+        }
+        if idx < start + len {
+            return start - shift;
+        }
+        shift += len;
+    }
+    idx - shift
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instrument_puts_calls_before_statements() {
+        let source = "x = 1\n// comment\n\nfn draw() {\n    put \"a\"\n}";
+        let (out, inserted) = instrument(source);
+        assert_eq!(
+            out,
+            "__bp(1, 0)\nx = 1\n// comment\n\n__bp(4, 29)\nfn draw() {\n    __bp(5, 53)\n    put \"a\"\n}"
+        );
+        assert_eq!(inserted, vec![(0, 11), (29, 12), (53, 16)]);
+    }
+
+    #[test]
+    fn instrument_calls_know_where_they_are() {
+        let source = "a = 1\nfn draw() {\n  put \"é\"\n  b = 2\n}\nc = 3";
+        let (out, inserted) = instrument(source);
+        let chars: Vec<char> = out.chars().collect();
+        for (start, len) in inserted {
+            let call: String = chars[start..start + len].iter().collect();
+            assert!(
+                call.trim().ends_with(&format!(", {})", start)),
+                "{:?} at {}",
+                call,
+                start
+            );
+        }
+    }
+
+    #[test]
+    fn instrument_skips_continued_lines_and_else() {
+        let source = "y = add(1,\n  2)\nif y {\n  put y\n}\nelse {\n  put 0\n}";
+        let (out, _) = instrument(source);
+        assert_eq!(
+            out,
+            "__bp(1, 0)\ny = add(1,\n  2)\n__bp(3, 27)\nif y {\n  __bp(4, 46)\n  put y\n}\nelse {\n  __bp(7, 77)\n  put 0\n}"
+        );
+    }
+
+    #[test]
+    fn instrument_leaves_multiline_strings_alone() {
+        let source = "s = \"one\ntwo \\\" {\nthree\"\nput s";
+        let (out, _) = instrument(source);
+        assert_eq!(
+            out,
+            "__bp(1, 0)\ns = \"one\ntwo \\\" {\nthree\"\n__bp(4, 36)\nput s"
+        );
+        // a quote in a comment doesn't start a string
+        let (out, _) = instrument("a = 1 // \"\nb = 2");
+        assert_eq!(out, "__bp(1, 0)\na = 1 // \"\n__bp(2, 22)\nb = 2");
+    }
+
+    #[test]
+    fn instrument_skips_lines_of_literals() {
+        let source = "p = {\n  x: 1,\n  y: f({\n    z: 2\n  })\n}\nput p";
+        let (out, _) = instrument(source);
+        assert_eq!(
+            out,
+            "__bp(1, 0)\np = {\n  x: 1,\n  y: f({\n    z: 2\n  })\n}\n__bp(7, 50)\nput p"
+        );
+        // a block inside a literal still holds statements
+        let source = "f(fn() {\n  put 1\n})";
+        let (out, _) = instrument(source);
+        assert_eq!(out, "__bp(1, 0)\nf(fn() {\n  __bp(2, 20)\n  put 1\n})");
+    }
+
+    #[test]
+    fn instrument_skips_lines_that_start_with_an_operator() {
+        let source = "x = 1\n  + 2\n  * 3\ny = x\n  == 9\n  && true";
+        let (out, _) = instrument(source);
+        assert_eq!(
+            out,
+            "__bp(1, 0)\nx = 1\n  + 2\n  * 3\n__bp(4, 29)\ny = x\n  == 9\n  && true"
+        );
+    }
+
+    #[test]
+    fn hit_keeps_what_the_ui_sent_while_stopped() {
+        zvm::initstate(Default::default());
+        let mut answers = vec![
+            GameCommand::Debug(DebugCommand::Continue),
+            GameCommand::Input {
+                keys: vec!["b".to_string()],
+                mouse: None,
+            },
+            GameCommand::Pause,
+            GameCommand::Input {
+                keys: vec!["a".to_string()],
+                mouse: None,
+            },
+        ];
+        zvm::with_state(|s| {
+            s.debug.hook = Some(Box::new(move |_: &BreakInfo| answers.pop()));
+            s.debug.breakpoints = vec![BTreeSet::from([3])];
+            s.debug.enter(0, "player", DRAW);
+        });
+        assert!(hit(3, 0).is_ok());
+        let queued = zvm::with_state(|s| std::mem::take(&mut s.debug.queued));
+        assert!(matches!(
+            queued.as_slice(),
+            [GameCommand::Pause, GameCommand::Input { keys, .. }] if keys == &["b"]
+        ));
+        assert_eq!(zvm::with_state(|s| s.debug.mode), StepMode::Run);
+    }
+
+    #[test]
+    fn unmap_finds_the_original_chars() {
+        let source = "x = 1\nwhile x < 3 {\n  x = x + 1\n}\n\nfn draw() {\n    put \"é\"\n}";
+        let (out, inserted) = instrument(source);
+        let original: Vec<char> = source.chars().collect();
+        let instrumented: Vec<char> = out.chars().collect();
+        for (i, c) in instrumented.iter().enumerate() {
+            let inside = inserted
+                .iter()
+                .any(|&(start, len)| i >= start && i < start + len);
+            if !inside {
+                assert_eq!(original[unmap(i, &inserted)], *c, "char {} of {:?}", i, out);
+            }
+        }
+    }
+
+    #[test]
+    fn unmap_points_inserted_calls_at_their_line() {
+        let (out, inserted) = instrument("a = 1\nb = 2");
+        assert_eq!(out, "__bp(1, 0)\na = 1\n__bp(2, 17)\nb = 2");
+        assert_eq!(inserted, vec![(0, 11), (17, 12)]);
+        // anywhere in a call is where the line it was put before starts
+        assert_eq!(unmap(3, &inserted), 0);
+        assert_eq!(unmap(17, &inserted), 6);
+        assert_eq!(unmap(28, &inserted), 6);
+        assert_eq!(unmap(33, &inserted), 10);
+    }
+}
@@ -0,0 +1,75 @@
+use crate::debugger::DebugCommand;
+use crate::gamewindow::GameWindow;
+use egui::{Color32, RichText};
+
+#[derive(Default)]
+pub struct DebugWindow {
+    pub enabled: bool,
+}
+impl DebugWindow {
+    pub fn ui(&mut self, game: &mut GameWindow, ui: &mut egui::Ui) {
+        if game.game.is_none() {
+            ui.label("tick \"debug\" next to Play, then click a line number in the code window to set a breakpoint");
+            return;
+        }
+        if !game.debug {
+            ui.label("this game was started without \"debug\", restart it to use breakpoints");
+            return;
+        }
+        let brk = match game.brk.clone() {
+            Some(brk) => brk,
+            None => {
+                ui.label("running");
+                return;
+            }
+        };
+        ui.label(format!(
+            "stopped at line {} of {}",
+            brk.line, brk.objectname
+        ));
+        ui.horizontal(|ui| {
+            if ui.button("Continue").clicked() {
+                game.debugcommand(DebugCommand::Continue);
+            }
+            if ui.button("Step over").clicked() {
+                game.debugcommand(DebugCommand::StepOver);
+            }
+            if ui.button("Step into").clicked() {
+                game.debugcommand(DebugCommand::StepInto);
+            }
+        });
+        ui.separator();
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            egui::CollapsingHeader::new("Call stack")
+                .default_open(true)
+                .show(ui, |ui| {
+                    for frame in &brk.stack {
+                        ui.monospace(frame);
+                    }
+                });
+            egui::CollapsingHeader::new(if brk.toplevel {
+                "Variables (root scope, current)"
+            } else {
+                "Variables (root scope)"
+            })
+            .default_open(true)
+            .show(ui, |ui| {
+                if !brk.toplevel {
+                    ui.label(
+                        RichText::new("locals of the running function are kept inside zsp_core")
+                            .color(Color32::GRAY),
+                    );
+                }
+                egui::Grid::new("debug_variables")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for (name, value) in &brk.variables {
+                            ui.monospace(name);
+                            ui.monospace(value);
+                            ui.end_row();
+                        }
+                    });
+            });
+        });
+    }
+}
@@ -1,142 +1,470 @@
-use crate::app::Cart;
-use crate::spriteswindow::Sprite;
-// use c
-use crate::zvm::{self, ZEvent, ZVMState, STATE_PTR, ZVM};
-use crate::FanzApp;
-use array2d::Array2D;
+use crate::cart::{Cart, LAYERS};
+use crate::debugger::{BreakInfo, DebugCommand};
+use crate::profiler::Profiler;
+use crate::runner::{Frame, GameCommand, GameHandle, GameMessage, WatchObject, WatchValue};
 use egui::{
-    pos2, Align2, Color32, Id, Key, LayerId, Painter, Pos2, Rect, RichText, Sense, Stroke, Vec2,
-    Widget,
+    pos2, Align2, Color32, ColorImage, Key, Painter, Rect, RichText, Sense, Shape, TextureHandle,
+    Vec2,
 };
-use zsp_core::exceptions::Exception;
+use instant::Instant;
+use std::collections::BTreeSet;
 
-const WIDTH: u32 = 160;
-pub struct GameWindow<'a> {
+pub struct GameWindow {
     pub enabled: bool,
-    pub game: Option<Game<'a>>, // pub vm: Option<ZVM<'a>>,
+    pub game: Option<GameHandle>,
+    /// the last frame the game sent
+    pub texture: Option<TextureHandle>,
+    /// the newest frame `poll` got that isn't on the texture yet
+    latest: Option<Frame>,
+    pub scalemode: ScaleMode,
+    pub crt: bool,
+    /// set when a script blows its budget, so a runaway loop stops the game instead of the editor
+    pub paused: bool,
+    /// number of the last frame the game sent
+    pub frame: u64,
+    /// how many frames "Step N" runs
+    pub stepcount: u32,
+    /// reload scripts on their own once editing stops for a moment
+    pub autoreload: bool,
+    /// the scripts the game is running
+    scripts: Vec<String>,
+    /// the scripts as they were last seen in the cart, and when they last changed
+    pending: Vec<String>,
+    editedat: Instant,
+    /// start games instrumented for the debugger
+    pub debug: bool,
+    /// where the game is stopped, if it's sitting at a breakpoint
+    pub brk: Option<BreakInfo>,
+    /// set when the game stops at a breakpoint, so the editor can show the object's code
+    pub jumpto: Option<usize>,
+    /// the breakpoints the game has
+    breakpoints: Vec<BTreeSet<usize>>,
+    /// the latest variables of every object, while watching
+    pub watch: Vec<WatchObject>,
+    watching: bool,
+    pub profiler: Profiler,
+    /// layers the game draws, for looking at one without the others
+    pub layers: [bool; LAYERS.len()],
 }
-
-pub struct Game<'a> {
-    pub objects: Vec<GameObject<'a>>,
-}
-pub struct GameObject<'a> {
-    pub vm: ZVM<'a>,
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ScaleMode {
+    /// biggest whole number scale that fits, so every game pixel is the same size
+    Integer,
+    /// fills as much space as possible, pixels may end up different sizes
+    Fit,
 }
 
-impl<'a> Default for GameWindow<'a> {
+impl Default for GameWindow {
     fn default() -> Self {
         GameWindow {
             enabled: false,
             game: None,
+            texture: None,
+            latest: None,
+            scalemode: ScaleMode::Integer,
+            crt: false,
+            paused: false,
+            frame: 0,
+            stepcount: 10,
+            autoreload: false,
+            scripts: vec![],
+            pending: vec![],
+            editedat: Instant::now(),
+            debug: false,
+            brk: None,
+            jumpto: None,
+            breakpoints: vec![],
+            watch: vec![],
+            watching: false,
+            profiler: Profiler::default(),
+            layers: [true; LAYERS.len()],
         }
     }
 }
-impl<'a> GameWindow<'a> {
-    pub fn ui(&mut self, app: &mut FanzApp<'a>, ui: &mut egui::Ui) {
-        let scalefactor =
-            f32::floor(ui.available_width() / 160.0).min(f32::floor(ui.available_width() / 120.0));
+impl GameWindow {
+    /// starts a fresh game from a copy of the cart, on its own thread
+    pub fn play(&mut self, cart: &Cart) {
+        self.paused = false;
+        self.frame = 0;
+        self.texture = None;
+        self.scripts = scripts(cart);
+        self.pending = self.scripts.clone();
+        self.brk = None;
+        self.breakpoints = breakpoints(cart);
+        self.watch.clear();
+        self.profiler.clear();
+        // the new game isn't watching yet
+        self.watching = false;
+        // replacing the old handle hangs up on the old game, which makes its thread quit.
+        // if it's stopped at a breakpoint that also unwinds it
+        let mut game = GameHandle::spawn(cart.clone(), self.debug);
+        if self.layers.contains(&false) {
+            game.send(GameCommand::Layers(self.layers));
+        }
+        self.game = Some(game);
+    }
+    /// hangs up on the game and clears what it left on screen
+    pub fn stop(&mut self) {
+        self.game = None;
+        self.brk = None;
+        self.latest = None;
+        self.texture = None;
+    }
+    pub fn pause(&mut self) {
+        self.paused = true;
+        if let Some(game) = &mut self.game {
+            game.send(GameCommand::Pause);
+        }
+    }
+    pub fn resume(&mut self) {
+        self.paused = false;
+        // a game stopped at a breakpoint takes this as continue
+        self.brk = None;
+        if let Some(game) = &mut self.game {
+            game.send(GameCommand::Resume);
+        }
+    }
+    /// runs `frames` frames and pauses again
+    pub fn step(&mut self, frames: u32) {
+        self.paused = true;
+        if let Some(game) = &mut self.game {
+            game.send(GameCommand::Step(frames));
+        }
+    }
+    /// sends every edited script to the running game.
+    /// does nothing while stopped at a breakpoint, the script that's stopped can't be swapped out
+    pub fn reload(&mut self, cart: &Cart) {
+        if self.brk.is_some() {
+            return;
+        }
+        self.scripts = scripts(cart);
+        self.pending = self.scripts.clone();
+        if let Some(game) = &mut self.game {
+            game.send(GameCommand::Reload {
+                names: cart.objects.iter().map(|o| o.name.clone()).collect(),
+                scripts: self.scripts.clone(),
+            });
+        }
+    }
+    /// reloads once the scripts have sat unchanged for half a second,
+    /// so half typed code doesn't spam the console with parse errors
+    pub fn autoreload(&mut self, cart: &Cart) {
+        if !self.autoreload || self.game.is_none() {
+            return;
+        }
+        let current = scripts(cart);
+        if current != self.pending {
+            self.pending = current;
+            self.editedat = Instant::now();
+        } else if self.pending != self.scripts && self.editedat.elapsed().as_millis() > 500 {
+            self.reload(cart);
+        }
+    }
+    /// tells a game stopped at a breakpoint how to go on
+    pub fn debugcommand(&mut self, cmd: DebugCommand) {
+        if self.brk.take().is_some() {
+            if let Some(game) = &mut self.game {
+                game.send(GameCommand::Debug(cmd));
+            }
+        }
+    }
+    /// sends breakpoints toggled in the editor to the running game
+    pub fn syncbreakpoints(&mut self, cart: &Cart) {
+        if !self.debug || self.game.is_none() {
+            return;
+        }
+        let current = breakpoints(cart);
+        if current != self.breakpoints {
+            self.breakpoints = current.clone();
+            if let Some(game) = &mut self.game {
+                game.send(GameCommand::Breakpoints(current));
+            }
+        }
+    }
+    /// asks the game to send its variables every frame, or to stop
+    pub fn setwatching(&mut self, watching: bool) {
+        if watching == self.watching {
+            return;
+        }
+        if let Some(game) = &mut self.game {
+            self.watching = watching;
+            game.send(GameCommand::Watch(watching));
+        }
+        if !watching {
+            self.watch.clear();
+        }
+    }
+    pub fn setvariable(&mut self, object: u64, name: String, value: WatchValue) {
+        if let Some(game) = &mut self.game {
+            game.send(GameCommand::SetVariable {
+                object,
+                name,
+                value,
+            });
+        }
+    }
+    /// play, stop, pause and stepping, for the top bar
+    pub fn controls_ui(&mut self, cart: &Cart, ui: &mut egui::Ui) {
+        if self.game.is_none() {
+            if ui.button("Play").clicked() {
+                self.play(cart);
+                self.enabled = true;
+            }
+            ui.checkbox(&mut self.debug, "debug")
+                .on_hover_text("lets the game stop at breakpoints, scripts run a bit slower");
+            return;
+        }
+        if ui.button("Stop").clicked() {
+            self.stop();
+            return;
+        }
+        if self.paused {
+            if ui.button("Resume").on_hover_text("Ctrl+P").clicked() {
+                self.resume();
+            }
+        } else if ui.button("Pause").on_hover_text("Ctrl+P").clicked() {
+            self.pause();
+        }
+        if ui.button("Step").on_hover_text("Ctrl+F").clicked() {
+            self.step(1);
+        }
+        if ui
+            .button(format!("Step {}", self.stepcount))
+            .on_hover_text("Ctrl+Shift+F")
+            .clicked()
+        {
+            self.step(self.stepcount);
+        }
+        ui.add(egui::DragValue::new(&mut self.stepcount).clamp_range(1..=600));
+        if ui
+            .button("Reload scripts")
+            .on_hover_text("Ctrl+R")
+            .clicked()
+        {
+            self.reload(cart);
+        }
+        ui.checkbox(&mut self.autoreload, "auto");
+    }
+    /// Ctrl+P pauses and resumes, Ctrl+F steps one frame, Ctrl+Shift+F steps `stepcount` frames,
+    /// Ctrl+R reloads scripts
+    pub fn shortcuts(&mut self, cart: &Cart, ctx: &egui::Context) {
+        // not while typing, the code editor has its own uses for these
+        if self.game.is_none() || ctx.memory().focus().is_some() {
+            return;
+        }
+        let (pause, step, stepmany, reload) = {
+            let input = ctx.input();
+            let ctrl = input.modifiers.command;
+            let shift = input.modifiers.shift;
+            (
+                ctrl && input.key_pressed(Key::P),
+                ctrl && !shift && input.key_pressed(Key::F),
+                ctrl && shift && input.key_pressed(Key::F),
+                ctrl && input.key_pressed(Key::R),
+            )
+        };
+        if pause {
+            if self.paused {
+                self.resume();
+            } else {
+                self.pause();
+            }
+        }
+        if step {
+            self.step(1);
+        }
+        if stepmany {
+            self.step(self.stepcount);
+        }
+        if reload {
+            self.reload(cart);
+        }
+    }
+    pub fn settings_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.scalemode, ScaleMode::Integer, "integer");
+            ui.selectable_value(&mut self.scalemode, ScaleMode::Fit, "fit");
+            ui.checkbox(&mut self.crt, "crt");
+            ui.checkbox(&mut self.profiler.enabled, "profiler");
+            ui.menu_button("layers", |ui| {
+                let mut changed = false;
+                for (visible, name) in self.layers.iter_mut().zip(LAYERS) {
+                    changed |= ui.checkbox(visible, name).changed();
+                }
+                if changed {
+                    if let Some(game) = &mut self.game {
+                        game.send(GameCommand::Layers(self.layers));
+                    }
+                }
+            });
+            if self.paused && self.game.is_some() && ui.button("Resume").clicked() {
+                self.resume();
+            }
+            if self.game.is_some() {
+                ui.label(format!("frame {}", self.frame));
+            }
+        });
+    }
+    pub fn ui(&mut self, cart: &Cart, ui: &mut egui::Ui) {
+        let (width, height) = (cart.width as f32, cart.height as f32);
+        let available = ui.available_size().max(Vec2::new(width, height));
+        let fit = (available.x / width).min(available.y / height);
+        let scalefactor = match self.scalemode {
+            ScaleMode::Integer => fit.floor().max(1.0),
+            ScaleMode::Fit => fit,
+        };
 
-        let (_resp, painter) = ui.allocate_painter(
-            Vec2::new(160.0 * scalefactor, 120.0 * scalefactor),
-            Sense::click_and_drag(),
+        // take all the space we're given and center the screen in it, the rest is letterboxing
+        let (resp, fullpainter) = ui.allocate_painter(available, Sense::click_and_drag());
+        fullpainter.rect_filled(fullpainter.clip_rect(), 0.0, Color32::BLACK);
+        let screen = Rect::from_center_size(
+            resp.rect.center(),
+            Vec2::new(width * scalefactor, height * scalefactor),
         );
+        let painter = fullpainter.with_clip_rect(screen.intersect(fullpainter.clip_rect()));
 
-        match &mut self.game {
-            Some(game) => {
-                let mut state = unsafe { &mut *STATE_PTR };
+        let game = match &mut self.game {
+            Some(game) => game,
+            None => return,
+        };
 
-                for obj in &mut game.objects {
-                    match obj.vm.draw() {
-                        Err(e) => {
-                            let o = obj.vm.fmt(e);
-                            app.output
-                                .push(RichText::new(o.to_string()).color(Color32::RED));
-                        }
-                        _ => (),
-                    }
-                }
+        if let Some(frame) = self.latest.take() {
+            let uploadstart = Instant::now();
+            let image = upscale(&frame, scalefactor);
+            match &mut self.texture {
+                Some(texture) => texture.set(image),
+                None => self.texture = Some(ui.ctx().load_texture("game", image)),
+            }
+            self.profiler
+                .uploaded(frame.number, uploadstart.elapsed().as_micros() as u64);
+        }
 
-                let start = painter.clip_rect().min;
-                painter.rect_filled(painter.clip_rect(), 0.0, Color32::BLACK);
-                for i in &state.buffer {
-                    match i {
-                        ZEvent::Put(s) => {
-                            if app.output.len() > 100 {
-                                app.output.clear();
-                            }
-                            app.output.push(RichText::new(s))
-                        }
-                        ZEvent::GSet { x, y, color } => {
-                            drawpixel(&painter, scalefactor, start, *x, *y, *color)
-                        }
-                        ZEvent::Rect { x, y, h, w, color } => painter.rect_filled(
-                            Rect::from_min_size(
-                                pos2(start.x + x * scalefactor, start.y + y * scalefactor),
-                                Vec2 {
-                                    x: *w * scalefactor,
-                                    y: *h * scalefactor,
-                                },
-                            ),
-                            0.0,
-                            *color,
-                        ),
-                        ZEvent::Sprite {
-                            x: sx,
-                            y: sy,
-                            sprite,
-                        } => {
-                            let spritedata = &app.cart.sprites[*sprite].data;
-                            for x in 0..8 {
-                                for y in 0..8 {
-                                    drawpixel(
-                                        &painter,
-                                        scalefactor,
-                                        start,
-                                        *sx + x as f32,
-                                        *sy + y as f32,
-                                        spritedata.get(x, y).unwrap().clone(),
-                                    )
-                                }
-                            }
-                        }
+        if let Some(texture) = &self.texture {
+            painter.add(Shape::image(
+                texture.id(),
+                screen,
+                Rect::from_min_max(pos2(0.0, 0.0), pos2(1.0, 1.0)),
+                Color32::WHITE,
+            ));
+        }
+        if self.crt {
+            scanlines(&painter, screen, scalefactor);
+        }
+        if self.profiler.enabled {
+            self.profiler
+                .overlay(&fullpainter, screen.intersect(fullpainter.clip_rect()));
+        }
+        let status = match &self.brk {
+            Some(brk) => Some(format!(
+                "stopped at line {} of {}",
+                brk.line, brk.objectname
+            )),
+            None if self.paused => Some("paused".to_string()),
+            None => None,
+        };
+        if let Some(status) = status {
+            // in the corner, so it doesn't cover the frame while stepping through it
+            fullpainter.text(
+                screen.left_top() + Vec2::new(4.0, 4.0),
+                Align2::LEFT_TOP,
+                status,
+                egui::FontId::monospace(14.0),
+                Color32::WHITE,
+            );
+        }
+
+        let keys = ui
+            .input()
+            .keys_down
+            .iter()
+            .map(|k| format!("{:?}", k))
+            .collect();
+        // pointer position in game pixels
+        let mouse = resp.hover_pos().filter(|p| screen.contains(*p)).map(|p| {
+            let p = (p - screen.min) / scalefactor;
+            (p.x.floor(), p.y.floor())
+        });
+        game.send(GameCommand::Input { keys, mouse });
+    }
+    /// takes in what the game sent. call it every update, even when the window isn't shown,
+    /// or the game's messages pile up and a breakpoint never shows
+    pub fn poll(&mut self, output: &mut Vec<RichText>, ctx: &egui::Context) {
+        let game = match &mut self.game {
+            Some(game) => game,
+            None => return,
+        };
+        let mut exited = false;
+        for msg in game.poll() {
+            match msg {
+                // the ui may run slower than the game, only the newest frame matters
+                GameMessage::Frame(f) => {
+                    self.frame = f.number;
+                    self.latest = Some(f)
+                }
+                GameMessage::Put(s) => {
+                    if output.len() > 100 {
+                        output.clear();
                     }
+                    output.push(RichText::new(s))
                 }
-                state.buffer = vec![];
-
-                state.keys.clear();
-                for i in ui.input().keys_down.iter() {
-                    state.keys.push(format!("{:?}", i));
+                GameMessage::Error(e) => output.push(RichText::new(e).color(Color32::RED)),
+                GameMessage::Reloaded(name) => {
+                    output.push(RichText::new(format!("reloaded {}", name)).color(Color32::GREEN))
                 }
-
-                ui.ctx().request_repaint();
+                GameMessage::Paused => self.paused = true,
+                GameMessage::Break(info) => {
+                    self.jumpto = Some(info.object);
+                    self.brk = Some(info);
+                }
+                GameMessage::Watch(objects) => self.watch = objects,
+                GameMessage::Profile(profile) => self.profiler.push(profile),
+                GameMessage::Exited => exited = true,
             }
-            None => (),
         }
+        if exited {
+            self.stop();
+        }
+        ctx.request_repaint();
     }
-    pub fn startgame(cart: &mut Cart) -> Result<Game<'a>, String> {
-        let mut objects = vec![];
+}
 
-        for editorobj in &cart.objects {
-            let vm = match ZVM::start(editorobj.script.clone()) {
-                Ok(vm) => vm,
-                Err(e) => {
-                    let o = zvm::errfmt(e, &editorobj.script);
-                    return Err(o);
-                }
-            };
-            objects.push(GameObject { vm: vm })
+/// textures get linear filtering, so blow the frame up by a whole number first to keep the pixels sharp
+fn upscale(frame: &Frame, scalefactor: f32) -> ColorImage {
+    const MAXSIDE: usize = 2048;
+    let k = (scalefactor.ceil() as usize)
+        .min(MAXSIDE / frame.width.max(frame.height).max(1))
+        .max(1);
+    let mut image = ColorImage::new([frame.width * k, frame.height * k], Color32::BLACK);
+    for y in 0..frame.height * k {
+        for x in 0..frame.width * k {
+            image.pixels[y * frame.width * k + x] = frame.pixels[(y / k) * frame.width + x / k];
         }
-        Ok(Game { objects })
     }
+    image
 }
 
-fn drawpixel(painter: &Painter, scalefactor: f32, start: Pos2, x: f32, y: f32, color: Color32) {
-    painter.rect_filled(
-        Rect::from_min_size(
-            egui::pos2(start.x + x * scalefactor, start.y + y * scalefactor),
-            Vec2::new(scalefactor, scalefactor),
-        ),
-        0.0,
-        color,
-    )
+/// darkens the bottom half of every game pixel row, like the gaps between lines on a crt
+fn scanlines(painter: &Painter, screen: Rect, scalefactor: f32) {
+    if scalefactor < 2.0 {
+        return;
+    }
+    let mut y = screen.min.y;
+    while y < screen.max.y {
+        painter.rect_filled(
+            Rect::from_min_max(
+                pos2(screen.min.x, y + scalefactor * 0.5),
+                pos2(screen.max.x, (y + scalefactor).min(screen.max.y)),
+            ),
+            0.0,
+            Color32::from_black_alpha(90),
+        );
+        y += scalefactor;
+    }
+}
+
+fn scripts(cart: &Cart) -> Vec<String> {
+    cart.objects.iter().map(|o| o.script.clone()).collect()
+}
+fn breakpoints(cart: &Cart) -> Vec<BTreeSet<usize>> {
+    cart.objects.iter().map(|o| o.breakpoints.clone()).collect()
 }
@@ -1,12 +1,15 @@
+use std::collections::BTreeSet;
 use std::rc::Rc;
 use std::vec;
 
-use crate::app::{draw_sprite, EditorObject};
+use crate::app::draw_sprite;
+use crate::cart::{EditorObject, Scene, FLAGCOLORS};
 use crate::tab::Tab;
 use crate::zvm::{self, ZEvent, ZVMState, ZVM};
 use crate::FanzApp;
 use egui::{
-    pos2, Align2, Color32, Id, Key, LayerId, Painter, Rect, RichText, Sense, Stroke, Vec2, Widget,
+    pos2, vec2, Align2, Color32, Id, Key, LayerId, Painter, Rect, RichText, Sense, Stroke, Vec2,
+    Widget,
 };
 pub struct SpritesSelector;
 impl<'a> Tab<'a> for SpritesSelector {
@@ -31,6 +34,16 @@ impl<'a> Tab<'a> for SpritesSelector {
                     },
                 );
                 draw_sprite(&painter, rect, sprite);
+                // a dot along the bottom for every flag that's set
+                for (flag, color) in FLAGCOLORS.iter().enumerate() {
+                    if sprite.flag(flag) {
+                        painter.circle_filled(
+                            rect.left_bottom() + vec2(3.0 + flag as f32 * 3.7, -3.0),
+                            1.5,
+                            *color,
+                        );
+                    }
+                }
                 if response.clicked() {
                     app.selectedsprite = i;
                 }
@@ -41,25 +54,362 @@ impl<'a> Tab<'a> for SpritesSelector {
         });
     }
 }
-pub struct ObjectSelector;
+/// the objects of the scene being edited, as a tree of parents and children
+#[derive(Default)]
+pub struct ObjectSelector {
+    /// only objects whose name contains this, and their parents, are shown
+    pub filter: String,
+    /// object whose name is being edited in place
+    renaming: Option<usize>,
+    /// object waiting for "delete" to be confirmed
+    deleting: Option<usize>,
+    /// object being dragged to a new place in the list
+    dragging: Option<usize>,
+    /// objects whose children are hidden
+    collapsed: BTreeSet<usize>,
+    /// where every row was drawn this frame, for dropping
+    rows: Vec<(usize, Rect)>,
+    /// done once the whole tree is drawn, so indices don't move under it
+    actions: Vec<TreeAction>,
+}
+enum TreeAction {
+    Duplicate(usize),
+    AddChild(usize),
+    Delete(usize),
+    Move {
+        object: usize,
+        before: usize,
+    },
+    Reparent {
+        object: usize,
+        parent: Option<usize>,
+    },
+}
 impl<'a> Tab<'a> for ObjectSelector {
     fn name(&self) -> &str {
         "Objects"
     }
     fn ui(&mut self, app: &mut FanzApp<'a>, ui: &mut egui::Ui) {
-        if ui.button("add object").clicked() {
-            app.cart.objects.push(EditorObject {
-                pos: pos2(0.0, 0.0),
-                name: format!("Object {}", app.cart.objects.len()),
-                script: "put \"Hello World\"".into(),
+        ui.horizontal(|ui| {
+            if ui.button("add object").clicked() {
+                let mut obj = EditorObject::new(
+                    format!("Object {}", app.cart.objects.len()),
+                    pos2(0.0, 0.0),
+                    "put \"Hello World\"".into(),
+                );
+                obj.scene = app.scene;
+                app.cart.objects.push(obj);
+            }
+            ui.add(egui::TextEdit::singleline(&mut self.filter).hint_text("search"));
+        });
+
+        // what the filter lets through: matching objects and everything above them
+        let filter = self.filter.to_lowercase();
+        let mut shown = vec![filter.is_empty(); app.cart.objects.len()];
+        if !filter.is_empty() {
+            for i in 0..app.cart.objects.len() {
+                if app.cart.objects[i].name.to_lowercase().contains(&filter) {
+                    let mut current = Some(i);
+                    while let Some(c) = current {
+                        if shown[c] {
+                            break;
+                        }
+                        shown[c] = true;
+                        current = app.cart.parent(c);
+                    }
+                }
+            }
+        }
+
+        self.rows.clear();
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            self.tree_ui(app, ui, None, 0, &shown);
+        });
+        let rows = std::mem::take(&mut self.rows);
+        let mut actions = std::mem::take(&mut self.actions);
+
+        if let Some(object) = self.dragging {
+            let stroke = Stroke::new(2.0, Color32::WHITE);
+            let pointer = ui.input().pointer.interact_pos();
+            // the top of a row puts it in front, the rest of the row puts it under,
+            // below the last row makes it the last object at the top of the tree
+            let action = pointer.and_then(|p| {
+                if let Some((row, rect)) = rows
+                    .iter()
+                    .find(|(_, rect)| p.y >= rect.min.y && p.y < rect.max.y)
+                {
+                    if *row == object {
+                        return None;
+                    }
+                    if p.y < rect.min.y + rect.height() / 3.0 {
+                        ui.painter().hline(rect.x_range(), rect.min.y, stroke);
+                        return Some(TreeAction::Move {
+                            object,
+                            before: *row,
+                        });
+                    }
+                    ui.painter().rect_stroke(*rect, 2.0, stroke);
+                    return Some(TreeAction::Reparent {
+                        object,
+                        parent: Some(*row),
+                    });
+                }
+                let (_, last) = rows.last()?;
+                if p.y >= last.max.y && last.x_range().contains(&p.x) {
+                    ui.painter().hline(last.x_range(), last.max.y, stroke);
+                    return Some(TreeAction::Reparent {
+                        object,
+                        parent: None,
+                    });
+                }
+                None
             });
+            if ui.input().pointer.any_released() {
+                actions.extend(action);
+                self.dragging = None;
+            }
+        }
+
+        if let Some(object) = self.deleting.filter(|o| *o < app.cart.objects.len()) {
+            let children = app.cart.subtree(object).len() - 1;
+            let mut open = true;
+            egui::Window::new("Delete object")
+                .collapsible(false)
+                .resizable(false)
+                .open(&mut open)
+                .show(ui.ctx(), |ui| {
+                    ui.label(match children {
+                        0 => format!("delete {}?", app.cart.objects[object].name),
+                        n => format!(
+                            "delete {} and the {} objects under it?",
+                            app.cart.objects[object].name, n
+                        ),
+                    });
+                    ui.horizontal(|ui| {
+                        if ui.button("Delete").clicked() {
+                            actions.push(TreeAction::Delete(object));
+                            self.deleting = None;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.deleting = None;
+                        }
+                    });
+                });
+            if !open {
+                self.deleting = None;
+            }
         }
-        ui.vertical(|ui| {
-            for (i, o) in app.cart.objects.iter().enumerate() {
-                if ui.button(&o.name).clicked() {
+
+        for action in actions {
+            let moved = match action {
+                TreeAction::Duplicate(object) => {
+                    app.selectedobject = app.cart.duplicate(object);
+                    self.collapsed.clear();
+                    continue;
+                }
+                TreeAction::AddChild(parent) => {
+                    let mut obj = EditorObject::new(
+                        format!("Object {}", app.cart.objects.len()),
+                        pos2(0.0, 0.0),
+                        "put \"Hello World\"".into(),
+                    );
+                    obj.scene = app.cart.objects[parent].scene;
+                    obj.parent = Some(parent);
+                    app.cart.objects.push(obj);
+                    self.collapsed.remove(&parent);
+                    app.selectedobject = app.cart.objects.len() - 1;
+                    continue;
+                }
+                TreeAction::Delete(object) => app.cart.removeobject(object),
+                TreeAction::Move { object, before } => app.cart.moveobject(object, before),
+                TreeAction::Reparent { object, parent } => app.cart.reparent(object, parent),
+            };
+            // when it's gone, something else in the scene
+            app.selectedobject = moved
+                .get(app.selectedobject)
+                .copied()
+                .flatten()
+                .or_else(|| {
+                    (0..app.cart.objects.len()).find(|i| app.cart.objects[*i].scene == app.scene)
+                })
+                .unwrap_or(0);
+            self.collapsed.clear();
+            self.renaming = None;
+        }
+    }
+}
+impl ObjectSelector {
+    /// the children of `parent` in cart order, each followed by its own children
+    fn tree_ui(
+        &mut self,
+        app: &mut FanzApp<'_>,
+        ui: &mut egui::Ui,
+        parent: Option<usize>,
+        depth: usize,
+        shown: &[bool],
+    ) {
+        let children: Vec<usize> = (0..app.cart.objects.len())
+            .filter(|i| {
+                app.cart.objects[*i].scene == app.scene
+                    && app.cart.parent(*i) == parent
+                    && shown[*i]
+            })
+            .collect();
+        for i in children {
+            let haschildren = (0..app.cart.objects.len()).any(|c| app.cart.parent(c) == Some(i));
+            // searching opens everything up, so matches aren't hidden under a closed parent
+            let open = !self.collapsed.contains(&i) || !self.filter.is_empty();
+            let row = ui.horizontal(|ui| {
+                ui.add_space(depth as f32 * 12.0);
+                let handle = ui
+                    .add(
+                        egui::Label::new(RichText::new("::").color(Color32::GRAY))
+                            .sense(Sense::drag()),
+                    )
+                    .on_hover_text("drag to move");
+                if handle.drag_started() {
+                    self.dragging = Some(i);
+                }
+                if haschildren {
+                    if ui.small_button(if open { "v" } else { ">" }).clicked() {
+                        if open {
+                            self.collapsed.insert(i);
+                        } else {
+                            self.collapsed.remove(&i);
+                        }
+                    }
+                } else {
+                    ui.add_space(ui.spacing().interact_size.y);
+                }
+                if self.renaming == Some(i) {
+                    let resp = ui.text_edit_singleline(&mut app.cart.objects[i].name);
+                    if resp.lost_focus() || ui.input().key_pressed(Key::Enter) {
+                        self.renaming = None;
+                    } else {
+                        resp.request_focus();
+                    }
+                    return;
+                }
+                let resp = ui
+                    .selectable_label(i == app.selectedobject, &app.cart.objects[i].name)
+                    .on_hover_text("double click to rename, right click for more");
+                if resp.clicked() {
                     app.selectedobject = i;
                 }
+                if resp.double_clicked() {
+                    self.renaming = Some(i);
+                }
+                resp.context_menu(|ui| {
+                    if ui.button("Rename").clicked() {
+                        self.renaming = Some(i);
+                        ui.close_menu();
+                    }
+                    if ui.button("Add child").clicked() {
+                        self.actions.push(TreeAction::AddChild(i));
+                        ui.close_menu();
+                    }
+                    if ui.button("Duplicate").clicked() {
+                        self.actions.push(TreeAction::Duplicate(i));
+                        ui.close_menu();
+                    }
+                    if ui.button("Delete...").clicked() {
+                        self.deleting = Some(i);
+                        ui.close_menu();
+                    }
+                });
+            });
+            self.rows.push((i, row.response.rect));
+            if haschildren && open {
+                self.tree_ui(app, ui, Some(i), depth + 1, shown);
             }
-        });
+        }
+    }
+}
+/// the cart's scenes. picking one switches the map and object list to it
+#[derive(Default)]
+pub struct SceneSelector {
+    /// scene waiting for "delete" to be confirmed
+    deleting: Option<usize>,
+}
+impl<'a> Tab<'a> for SceneSelector {
+    fn name(&self) -> &str {
+        "Scenes"
+    }
+    fn ui(&mut self, app: &mut FanzApp<'a>, ui: &mut egui::Ui) {
+        if ui.button("add scene").clicked() {
+            app.cart
+                .scenes
+                .push(Scene::new(format!("Scene {}", app.cart.scenes.len())));
+        }
+        ui.label(RichText::new("the game starts in the first scene").color(Color32::GRAY));
+        for i in 0..app.cart.scenes.len() {
+            ui.horizontal(|ui| {
+                if ui
+                    .selectable_label(i == app.scene, format!("{}", i))
+                    .clicked()
+                {
+                    app.scene = i;
+                    // keep the selection in the scene that's shown
+                    let inscene = app.cart.objects.get(app.selectedobject).map(|o| o.scene);
+                    if inscene != Some(i) {
+                        if let Some(first) = app.cart.objects.iter().position(|o| o.scene == i) {
+                            app.selectedobject = first;
+                        }
+                    }
+                }
+                ui.add(
+                    egui::TextEdit::singleline(&mut app.cart.scenes[i].name).desired_width(100.0),
+                );
+                if app.cart.scenes.len() > 1
+                    && ui
+                        .button("x")
+                        .on_hover_text("delete the scene and its objects")
+                        .clicked()
+                {
+                    self.deleting = Some(i);
+                }
+            });
+        }
+
+        let mut remove = None;
+        if let Some(scene) = self
+            .deleting
+            .filter(|s| *s < app.cart.scenes.len() && app.cart.scenes.len() > 1)
+        {
+            let objects = app.cart.objects.iter().filter(|o| o.scene == scene).count();
+            let mut open = true;
+            egui::Window::new("Delete scene")
+                .collapsible(false)
+                .resizable(false)
+                .open(&mut open)
+                .show(ui.ctx(), |ui| {
+                    ui.label(match objects {
+                        0 => format!("delete {}?", app.cart.scenes[scene].name),
+                        n => format!(
+                            "delete {}, its map and the {} objects in it?",
+                            app.cart.scenes[scene].name, n
+                        ),
+                    });
+                    ui.horizontal(|ui| {
+                        if ui.button("Delete").clicked() {
+                            remove = Some(scene);
+                            self.deleting = None;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.deleting = None;
+                        }
+                    });
+                });
+            if !open {
+                self.deleting = None;
+            }
+        }
+        if let Some(i) = remove {
+            app.cart.removescene(i);
+            if app.scene >= i && app.scene > 0 {
+                app.scene -= 1;
+            }
+            app.selectedobject = 0;
+        }
     }
 }
@@ -1,20 +1,53 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
-mod app;
+mod cart;
+mod collision;
 mod consolebuiltins;
+mod debugger;
 mod zvm;
 
+mod gamewindow;
+mod player;
+mod profiler;
+mod runner;
+
+// everything below is only needed by the editor, the standalone player is built without it
+#[cfg(feature = "editor")]
+mod app;
+#[cfg(feature = "editor")]
 mod tab;
+#[cfg(feature = "editor")]
 mod viewport;
 
+#[cfg(feature = "editor")]
 mod codewindow;
-mod gamewindow;
+#[cfg(feature = "editor")]
+mod debugwindow;
+#[cfg(feature = "editor")]
 mod leftpanel;
+#[cfg(feature = "editor")]
+mod mapexport;
+#[cfg(feature = "editor")]
 mod mapwindow;
+#[cfg(feature = "editor")]
+mod pico8;
+#[cfg(feature = "editor")]
 mod propertieswindow;
+#[cfg(feature = "editor")]
+mod spritesheet;
+#[cfg(feature = "editor")]
 mod spriteswindow;
+#[cfg(feature = "editor")]
+mod tiled;
+#[cfg(feature = "editor")]
+mod watchwindow;
+#[cfg(feature = "editor")]
+mod webexport;
 
+#[cfg(feature = "editor")]
 pub use app::FanzApp;
+pub use cart::Cart;
+pub use player::PlayerApp;
 // ----------------------------------------------------------------------------
 // When compiling for web:
 
@@ -25,7 +58,7 @@ use eframe::wasm_bindgen::{self, prelude::*};
 /// This is called once from the HTML.
 /// It loads the app, installs some callbacks, then returns.
 /// You can add more callbacks like this if you want to call in to your code.
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(target_arch = "wasm32", feature = "editor"))]
 #[wasm_bindgen]
 pub fn start(canvas_id: &str) -> Result<(), eframe::wasm_bindgen::JsValue> {
     // Make sure panics are logged using `console.error`.
@@ -36,3 +69,15 @@ pub fn start(canvas_id: &str) -> Result<(), eframe::wasm_bindgen::JsValue> {
 
     eframe::start_web(canvas_id, Box::new(|cc| Box::new(FanzApp::new(cc))))
 }
+
+/// Entry-point for exported games. Boots the cart straight into the game, without the editor.
+/// `cart` is the cart serialized as json, see `webexport`.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+pub fn start_player(canvas_id: &str, cart: &str) -> Result<(), eframe::wasm_bindgen::JsValue> {
+    console_error_panic_hook::set_once();
+    tracing_wasm::set_as_global_default();
+
+    let app = PlayerApp::from_json(cart).map_err(|e| JsValue::from_str(&e))?;
+    eframe::start_web(canvas_id, Box::new(|_cc| Box::new(app)))
+}
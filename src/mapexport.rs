@@ -0,0 +1,69 @@
+use crate::app::{blend, draw_sprite_image};
+use crate::cart::Cart;
+pub use crate::cart::TILESIZE;
+use crate::spritesheet;
+use crate::FanzApp;
+use egui::{Color32, RichText};
+use image::{Rgba, RgbaImage};
+
+/// renders a scene's whole map into an image, every tile being `TILESIZE * scale` pixels wide
+pub fn map_to_image(cart: &Cart, scene: usize, scale: u32, grid: bool) -> RgbaImage {
+    let scale = scale.max(1);
+    let tilepx = TILESIZE * scale;
+    let map = &cart.scenes[scene].map;
+    let mut img = RgbaImage::new(
+        (map.num_rows() as u32 * tilepx).max(1),
+        (map.num_columns() as u32 * tilepx).max(1),
+    );
+    for x in 0..map.num_rows() {
+        for y in 0..map.num_columns() {
+            if let Some(spr) = map.get(x, y).unwrap().and_then(|s| cart.sprites.get(s)) {
+                draw_sprite_image(
+                    &mut img,
+                    (x as u32 * tilepx, y as u32 * tilepx),
+                    (tilepx, tilepx),
+                    spr,
+                );
+            }
+        }
+    }
+    if grid {
+        let gridcolor = Color32::from_rgba_unmultiplied(255, 255, 255, 96);
+        for px in 0..img.width() {
+            for py in 0..img.height() {
+                if px % tilepx == 0 || py % tilepx == 0 {
+                    let under = img.get_pixel(px, py).0;
+                    img.put_pixel(px, py, Rgba(blend(gridcolor, under)));
+                }
+            }
+        }
+    }
+    img
+}
+
+pub fn export_dialog(app: &mut FanzApp<'_>, scale: u32, grid: bool) {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let path = match rfd::FileDialog::new()
+            .add_filter("png", &["png"])
+            .set_file_name("map.png")
+            .save_file()
+        {
+            Some(p) => p,
+            None => return,
+        };
+        let img = map_to_image(&app.cart, app.scene, scale, grid);
+        match spritesheet::save_png(&path, &img) {
+            Ok(()) => app.output.push(
+                RichText::new(format!("exported map to {}", path.display())).color(Color32::GREEN),
+            ),
+            Err(e) => app.output.push(RichText::new(e).color(Color32::RED)),
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = (scale, grid);
+        app.output
+            .push(RichText::new("png export is not supported on web").color(Color32::RED));
+    }
+}
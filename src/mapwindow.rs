@@ -1,6 +1,8 @@
 use std::fmt::Debug;
 
 use crate::app::draw_sprite;
+use crate::mapexport;
+use crate::tiled;
 use crate::viewport::Viewport;
 use crate::zvm::{self, ZEvent, ZVMState, ZVM};
 use crate::FanzApp;
@@ -19,6 +21,10 @@ pub struct MapWindow {
 
     #[serde(skip)]
     pub tool: Tool,
+
+    pub exportscale: u32,
+    pub exportgrid: bool,
+    pub tiledimages: bool,
 }
 
 pub enum Tool {
@@ -39,13 +45,16 @@ impl Default for MapWindow {
             viewport: Viewport::default(),
             enabled: false,
             tool: Tool::Pencil,
+            exportscale: 4,
+            exportgrid: false,
+            tiledimages: true,
         }
     }
 }
 impl MapWindow {
     pub fn ui<'a>(&mut self, app: &mut FanzApp<'a>, ui: &mut egui::Ui) {
         let tilesize = 32.0 / self.viewport.zoom;
-        let map = &mut app.cart.map;
+        let map = &mut app.cart.scenes[app.scene].map;
         ui.horizontal(|ui| {
             let mut columns = map.num_columns();
             let mut rows = map.num_rows();
@@ -69,15 +78,65 @@ impl MapWindow {
                 map.addrow(None);
             }
         });
-        let (resp, painter, start) = self.viewport.draw(
-            ui,
-            vec2(ui.available_width(), ui.available_height()),
+        ui.horizontal(|ui| {
+            ui.label("export scale: ");
+            ui.add(egui::DragValue::new(&mut self.exportscale).clamp_range(1..=32));
+            ui.checkbox(&mut self.exportgrid, "grid");
+            if ui.button("export png").clicked() {
+                mapexport::export_dialog(app, self.exportscale, self.exportgrid);
+            }
+            ui.separator();
+            ui.checkbox(&mut self.tiledimages, "load tileset images");
+            if ui.button("import tiled").clicked() {
+                tiled::import_dialog(app, self.tiledimages);
+            }
+            if ui.button("export tiled").clicked() {
+                let columns = app.spriteswindow.borrow().sheetcolumns;
+                tiled::export_dialog(app, columns);
+            }
+        });
+        let mapsize = {
+            let map = &app.cart.scenes[app.scene].map;
             vec2(
                 map.num_rows() as f32 * tilesize,
                 map.num_columns() as f32 * tilesize,
-            ),
+            )
+        };
+        let (resp, painter, start) = self.viewport.draw(
+            ui,
+            vec2(ui.available_width(), ui.available_height()),
+            mapsize,
         );
 
+        // objects sit on top of the map and can be dragged around. positions are in game pixels
+        let pixelsize = tilesize / mapexport::TILESIZE as f32;
+        let worldpos: Vec<Pos2> = (0..app.cart.objects.len())
+            .map(|i| app.cart.worldpos(i))
+            .collect();
+        let mut overobject = false;
+        for (i, obj) in app.cart.objects.iter_mut().enumerate() {
+            if obj.scene != app.scene {
+                continue;
+            }
+            let objrect = Rect::from_min_size(
+                start + worldpos[i].to_vec2() * pixelsize,
+                vec2(tilesize, tilesize),
+            );
+            let objresp = ui.interact(objrect, Id::new(("mapobject", i)), Sense::drag());
+            if objresp.drag_started() {
+                app.selectedobject = i;
+            }
+            if objresp.dragged() {
+                obj.pos += objresp.drag_delta() / pixelsize;
+            }
+            if objresp.drag_released() {
+                obj.pos = obj.pos.round();
+            }
+            overobject |= objresp.hovered() || objresp.dragged();
+        }
+
+        let map = &mut app.cart.scenes[app.scene].map;
+
         for x in 0..map.num_rows() {
             for y in 0..map.num_columns() {
                 let tilerect = Rect::from_min_size(
@@ -85,9 +144,8 @@ impl MapWindow {
                     vec2(tilesize, tilesize),
                 );
 
-                match map.get(x, y).unwrap() {
-                    Some(s) => draw_sprite(&painter, tilerect, &app.cart.sprites[*s]),
-                    None => (),
+                if let Some(sprite) = map.get(x, y).unwrap().and_then(|s| app.cart.sprites.get(s)) {
+                    draw_sprite(&painter, tilerect, sprite);
                 }
                 painter.rect_stroke(tilerect, 0f32, Stroke::new(2f32, Color32::WHITE));
 
@@ -101,7 +159,7 @@ impl MapWindow {
                                 _ => (),
                             }
                         }
-                        if tilerect.contains(*s) {
+                        if tilerect.contains(*s) && !overobject {
                             match self.tool {
                                 Tool::Pencil | Tool::Rect => {
                                     painter.rect_filled(tilerect.clone(), 0f32, Color32::BROWN);
@@ -169,6 +227,29 @@ impl MapWindow {
                 }
             }
         }
+
+        for (i, obj) in app.cart.objects.iter().enumerate() {
+            if obj.scene != app.scene {
+                continue;
+            }
+            let objrect = Rect::from_min_size(
+                start + worldpos[i].to_vec2() * pixelsize,
+                vec2(tilesize, tilesize),
+            );
+            let color = if i == app.selectedobject {
+                Color32::YELLOW
+            } else {
+                Color32::LIGHT_BLUE
+            };
+            painter.rect_stroke(objrect, 0f32, Stroke::new(2f32, color));
+            painter.text(
+                objrect.left_bottom(),
+                Align2::LEFT_TOP,
+                &obj.name,
+                egui::FontId::proportional(12.0),
+                color,
+            );
+        }
     }
 }
 
@@ -0,0 +1,287 @@
+use crate::cart::{Cart, EditorObject, Scene, Sprite, PICO8_PALETTE};
+use crate::FanzApp;
+use array2d::Array2D;
+use egui::{pos2, Color32, RichText};
+
+// zsp can't run lua, the code is kept around as a comment so it can be ported by hand
+const COMMENT: &str = "//";
+
+/// turns a .p8 text cart into a fan-z cart. returns the cart and a list of everything that got skipped
+pub fn parse_p8(input: &str) -> Result<(Cart, Vec<String>), String> {
+    if !input.starts_with("pico-8 cartridge") {
+        return Err("not a pico-8 text cart (.p8)".into());
+    }
+    let mut report = vec![];
+
+    let mut sections: Vec<(&str, Vec<&str>)> = vec![];
+    for line in input.lines() {
+        let trimmed = line.trim_end();
+        if trimmed.len() > 4 && trimmed.starts_with("__") && trimmed.ends_with("__") {
+            sections.push((trimmed, vec![]));
+        } else if let Some((_, lines)) = sections.last_mut() {
+            lines.push(line);
+        }
+    }
+    let section = |name: &str| {
+        sections
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, lines)| lines.clone())
+            .unwrap_or_default()
+    };
+
+    // __gfx__: 128 lines of 128 hex digits, one palette index per pixel. sprites are 8x8, 16 per row
+    let gfx = section("__gfx__");
+    let mut sprites = vec![];
+    for n in 0..256 {
+        let mut data = Array2D::filled_with(Color32::TRANSPARENT, 8, 8);
+        for y in 0..8 {
+            let line = match gfx.get((n / 16) * 8 + y) {
+                Some(l) => l.as_bytes(),
+                None => continue,
+            };
+            for x in 0..8 {
+                let idx = match line.get((n % 16) * 8 + x).and_then(|c| hexdigit(*c)) {
+                    Some(i) => i,
+                    None => continue,
+                };
+                // color 0 is transparent by default in pico-8
+                if idx != 0 {
+                    *data.get_mut(x, y).unwrap() = PICO8_PALETTE[idx as usize];
+                }
+            }
+        }
+        sprites.push(Sprite { data, flags: 0 });
+    }
+
+    // __gff__: 2 hex digits of flags per sprite
+    let gff = section("__gff__").concat();
+    for (n, sprite) in sprites.iter_mut().enumerate() {
+        if let Some(byte) = gff.get(n * 2..n * 2 + 2) {
+            sprite.flags = u8::from_str_radix(byte, 16).unwrap_or(0);
+        }
+    }
+
+    // __map__: 32 lines of 128 tiles, 2 hex digits each. tile 0 is never drawn
+    let maplines = section("__map__");
+    let mut map = Array2D::filled_with(None, 128, 32);
+    let mut used = 0;
+    for (y, line) in maplines.iter().take(32).enumerate() {
+        for x in 0..128 {
+            if let Some(tile) = line
+                .get(x * 2..x * 2 + 2)
+                .and_then(|b| usize::from_str_radix(b, 16).ok())
+            {
+                if tile != 0 {
+                    *map.get_mut(x, y).unwrap() = Some(tile);
+                    used = used.max(tile + 1);
+                }
+            }
+        }
+    }
+    if gfx.len() > 64 && gfx[64..].iter().any(|l| l.chars().any(|c| c != '0')) {
+        report.push(
+            "sprites 128-255 share memory with the bottom half of the pico-8 map, \
+             that half of the map was not imported"
+                .into(),
+        );
+    }
+
+    // drop the empty sprites at the end, keeping everything the map or flags refer to
+    let last = sprites
+        .iter()
+        .rposition(|s| s.flags != 0 || s.data.elements_row_major_iter().any(|c| c.a() != 0))
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    sprites.truncate(last.max(used));
+
+    let lua = section("__lua__");
+    let mut objects = vec![];
+    if lua.iter().any(|l| !l.trim().is_empty()) {
+        let mut script = format!(
+            "{} imported from pico-8. zsp can't run lua, port this by hand\n",
+            COMMENT
+        );
+        for line in &lua {
+            script.push_str(&format!("{} {}\n", COMMENT, line));
+        }
+        objects.push(EditorObject::new(
+            "pico-8 code".into(),
+            pos2(0.0, 0.0),
+            script,
+        ));
+        report.push(format!(
+            "{} lines of lua were kept as a comment in \"pico-8 code\"",
+            lua.len()
+        ));
+    }
+
+    for (name, lines) in &sections {
+        match *name {
+            "__lua__" | "__gfx__" | "__gff__" | "__map__" => (),
+            _ => {
+                if lines.iter().any(|l| !l.trim().is_empty()) {
+                    report.push(format!("{} section skipped", name));
+                }
+            }
+        }
+    }
+
+    Ok((
+        Cart {
+            sprites,
+            scenes: vec![Scene {
+                name: "main".to_string(),
+                map,
+            }],
+            objects,
+            palette: PICO8_PALETTE.to_vec(),
+            width: 128,
+            height: 128,
+            budget: Default::default(),
+            animations: vec![],
+        },
+        report,
+    ))
+}
+
+fn hexdigit(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
+}
+
+/// adds an imported cart to `cart`: the sprites go after the ones it has, the map replaces the one
+/// in `scene` and the objects go in that scene. returns the index of the first new sprite
+pub fn merge(cart: &mut Cart, scene: usize, imported: Cart) -> usize {
+    let base = cart.sprites.len();
+    cart.sprites.extend(imported.sprites);
+    if let Some(mut map) = imported.scenes.into_iter().next().map(|s| s.map) {
+        for x in 0..map.num_rows() {
+            for y in 0..map.num_columns() {
+                if let Some(tile) = map.get_mut(x, y).unwrap() {
+                    *tile += base;
+                }
+            }
+        }
+        cart.scenes[scene].map = map;
+    }
+    for mut obj in imported.objects {
+        obj.scene = scene;
+        obj.parent = None;
+        cart.objects.push(obj);
+    }
+    for color in imported.palette {
+        if !cart.palette.contains(&color) {
+            cart.palette.push(color);
+        }
+    }
+    base
+}
+
+/// imports a .p8 into the open cart, see `merge`
+pub fn import_dialog(app: &mut FanzApp<'_>) {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let path = match rfd::FileDialog::new()
+            .add_filter("pico-8 cart", &["p8"])
+            .pick_file()
+        {
+            Some(p) => p,
+            None => return,
+        };
+        let parsed = std::fs::read_to_string(&path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))
+            .and_then(|input| parse_p8(&input));
+        match parsed {
+            Ok((cart, report)) => {
+                // a map that's in use is left alone, the import gets a scene of its own
+                let inuse = app.cart.scenes[app.scene]
+                    .map
+                    .elements_row_major_iter()
+                    .any(Option::is_some);
+                if inuse {
+                    let name = path
+                        .file_stem()
+                        .map_or("pico-8".into(), |s| s.to_string_lossy().to_string());
+                    app.cart.scenes.push(Scene::new(name));
+                    app.scene = app.cart.scenes.len() - 1;
+                }
+                let objects = app.cart.objects.len();
+                app.selectedsprite = merge(&mut app.cart, app.scene, cart);
+                app.selectedobject = objects;
+                for skipped in report {
+                    app.output
+                        .push(RichText::new(skipped).color(Color32::YELLOW));
+                }
+                app.output.push(
+                    RichText::new(format!(
+                        "imported {} into scene \"{}\"",
+                        path.display(),
+                        app.cart.scenes[app.scene].name
+                    ))
+                    .color(Color32::GREEN),
+                );
+            }
+            Err(e) => app.output.push(RichText::new(e).color(Color32::RED)),
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    app.output
+        .push(RichText::new("pico-8 import is not supported on web").color(Color32::RED));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn p8() -> String {
+        // sprite 1 has a red pixel in the top left and flag bits 0 and 2, the map uses it twice
+        let mut gfx = vec!["0".repeat(128); 8];
+        gfx[0] = format!("00000000800000000{}", "0".repeat(111));
+        format!(
+            "pico-8 cartridge // http://www.pico-8.com\nversion 41\n__lua__\nfunction _draw()\n cls()\nend\n__gfx__\n{}\n__gff__\n0005\n__map__\n0001000001\n__sfx__\n0001000000\n",
+            gfx.join("\n")
+        )
+    }
+
+    #[test]
+    fn parse_p8_reads_sprites_flags_and_map() {
+        let (cart, report) = parse_p8(&p8()).unwrap();
+        // everything after the last sprite in use is dropped
+        assert_eq!(cart.sprites.len(), 2);
+        assert_eq!(*cart.sprites[1].data.get(0, 0).unwrap(), PICO8_PALETTE[8]);
+        assert_eq!(
+            *cart.sprites[1].data.get(1, 0).unwrap(),
+            Color32::TRANSPARENT
+        );
+        assert_eq!(cart.sprites[1].flags, 5);
+        assert_eq!(cart.sprites[0].flags, 0);
+
+        let map = &cart.scenes[0].map;
+        assert_eq!((map.num_rows(), map.num_columns()), (128, 32));
+        assert_eq!(map.get(0, 0), Some(&None));
+        assert_eq!(map.get(1, 0), Some(&Some(1)));
+        assert_eq!(map.get(4, 0), Some(&Some(1)));
+
+        assert_eq!(cart.objects.len(), 1);
+        assert!(cart.objects[0].script.contains("// function _draw()"));
+        assert!(report.iter().any(|r| r.contains("__sfx__")));
+    }
+
+    #[test]
+    fn parse_p8_rejects_other_files() {
+        assert!(parse_p8("{\"sprites\": []}").is_err());
+    }
+
+    #[test]
+    fn merge_goes_after_what_the_cart_has() {
+        let (imported, _) = parse_p8(&p8()).unwrap();
+        let mut cart = Cart::default();
+        cart.sprites.push(Sprite::new());
+        cart.scenes.push(Scene::new("second".into()));
+        assert_eq!(merge(&mut cart, 1, imported), 1);
+        assert_eq!(cart.sprites.len(), 3);
+        assert_eq!(cart.scenes[1].map.get(1, 0), Some(&Some(2)));
+        assert_eq!(cart.scenes[0].map.num_rows(), 8);
+        assert_eq!(cart.objects[0].scene, 1);
+    }
+}
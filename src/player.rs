@@ -0,0 +1,103 @@
+use crate::cart::Cart;
+use crate::gamewindow::GameWindow;
+use egui::{Align2, Color32, Key, RichText};
+
+/// runs a single cart full screen, without any of the editor around it
+pub struct PlayerApp {
+    pub cart: Cart,
+    pub gamewindow: GameWindow,
+    pub output: Vec<RichText>,
+    pub fullscreen: bool,
+}
+
+impl PlayerApp {
+    pub fn new(cart: Cart) -> Self {
+        let mut gamewindow = GameWindow::default();
+        gamewindow.play(&cart);
+        gamewindow.enabled = true;
+        PlayerApp {
+            cart,
+            gamewindow,
+            output: vec![],
+            fullscreen: false,
+        }
+    }
+    /// tells the player the window starts out fullscreen, so the toggle starts from the right state
+    pub fn fullscreen(mut self, fullscreen: bool) -> Self {
+        self.fullscreen = fullscreen;
+        self
+    }
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let cart: Cart = serde_json::from_str(json).map_err(|e| format!("bad cart: {}", e))?;
+        Ok(PlayerApp::new(cart))
+    }
+}
+
+impl eframe::App for PlayerApp {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
+        ctx.set_visuals(egui::style::Visuals::dark());
+        // a script running out of budget pauses the game too, which opens the menu instead of sitting on a frozen screen
+        if ctx.input().key_pressed(Key::Escape) {
+            if self.gamewindow.paused {
+                self.gamewindow.resume();
+            } else {
+                self.gamewindow.pause();
+            }
+        }
+        let togglefullscreen = {
+            let input = ctx.input();
+            input.modifiers.alt && input.key_pressed(Key::Enter)
+        };
+        if togglefullscreen {
+            self.fullscreen = !self.fullscreen;
+            frame.set_fullscreen(self.fullscreen);
+        }
+
+        self.gamewindow.poll(&mut self.output, ctx);
+        egui::CentralPanel::default()
+            .frame(egui::Frame::none().fill(Color32::BLACK))
+            .show(ctx, |ui| {
+                if self.gamewindow.game.is_none() {
+                    // the game failed to start or crashed, there's no console so show why here
+                    for line in &self.output {
+                        ui.code(line.clone().code());
+                    }
+                } else {
+                    self.gamewindow.ui(&self.cart, ui);
+                }
+            });
+
+        if self.gamewindow.paused && self.gamewindow.game.is_some() {
+            egui::Window::new("Paused")
+                .anchor(Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.vertical_centered(|ui| {
+                        if ui.button("Resume").clicked() {
+                            self.gamewindow.resume();
+                        }
+                        if ui.button("Restart").clicked() {
+                            self.output.clear();
+                            self.gamewindow.play(&self.cart);
+                        }
+                        let label = if self.fullscreen {
+                            "Windowed (Alt+Enter)"
+                        } else {
+                            "Fullscreen (Alt+Enter)"
+                        };
+                        if ui.button(label).clicked() {
+                            self.fullscreen = !self.fullscreen;
+                            frame.set_fullscreen(self.fullscreen);
+                        }
+                        ui.separator();
+                        self.gamewindow.settings_ui(ui);
+                        ui.separator();
+                        if ui.button("Quit").clicked() {
+                            frame.quit();
+                        }
+                    });
+                });
+        }
+    }
+}
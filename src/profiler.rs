@@ -0,0 +1,248 @@
+use egui::{pos2, Align2, Color32, FontId, Painter, Rect, Stroke, Vec2};
+use std::collections::VecDeque;
+
+/// how many frames are kept for the graph and the trace
+const HISTORY: usize = 600;
+/// how many of those the graph shows
+const GRAPHFRAMES: usize = 120;
+const FRAMEBUDGET: f32 = 1000.0 / 60.0;
+
+/// something that took time during a frame, in microseconds since the game started
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub name: String,
+    pub start: u64,
+    pub duration: u64,
+}
+/// where the time of one frame went, measured on the game thread
+#[derive(Debug, Clone)]
+pub struct FrameProfile {
+    pub number: u64,
+    /// one span per object's `draw`, then one for rendering the events
+    pub spans: Vec<Span>,
+    pub render: Span,
+    /// ZEvents of each kind: gset, rect, sprite, put
+    pub events: [usize; 4],
+    /// time spent putting the frame on a texture, measured on the ui thread. 0 if it was skipped
+    pub upload: u64,
+}
+pub const EVENTNAMES: [&str; 4] = ["gset", "rect", "sprite", "put"];
+impl FrameProfile {
+    pub fn start(&self) -> u64 {
+        self.spans.first().unwrap_or(&self.render).start
+    }
+    /// time the game thread spent on the frame, in ms
+    pub fn work(&self) -> f32 {
+        let end = self.render.start + self.render.duration;
+        (end - self.start()) as f32 / 1000.0
+    }
+}
+
+#[derive(Default)]
+pub struct Profiler {
+    /// shows the overlay
+    pub enabled: bool,
+    pub frames: VecDeque<FrameProfile>,
+}
+impl Profiler {
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+    pub fn push(&mut self, profile: FrameProfile) {
+        if self.frames.len() >= HISTORY {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(profile);
+    }
+    /// remembers how long uploading frame `number` took
+    pub fn uploaded(&mut self, number: u64, micros: u64) {
+        if let Some(f) = self.frames.iter_mut().rev().find(|f| f.number == number) {
+            f.upload = micros;
+        }
+    }
+
+    /// numbers in the top right, frame time graph under them
+    pub fn overlay(&self, painter: &Painter, screen: Rect) {
+        let last = match self.frames.back() {
+            Some(f) => f,
+            None => return,
+        };
+        let mut lines = vec![];
+        let fps = match self.frames.len() {
+            0 | 1 => 0.0,
+            n => {
+                let first = &self.frames[n.saturating_sub(GRAPHFRAMES)];
+                let frames = (last.number - first.number) as f32;
+                let secs = (last.start() - first.start()) as f32 / 1_000_000.0;
+                if secs > 0.0 {
+                    frames / secs
+                } else {
+                    0.0
+                }
+            }
+        };
+        lines.push(format!("frame {:.2}ms  {:.0} fps", last.work(), fps));
+        for span in &last.spans {
+            lines.push(format!(
+                "  {} {:.2}ms",
+                span.name,
+                span.duration as f32 / 1000.0
+            ));
+        }
+        lines.push(format!(
+            "render {:.2}ms  upload {:.2}ms",
+            last.render.duration as f32 / 1000.0,
+            last.upload as f32 / 1000.0
+        ));
+        lines.push(
+            EVENTNAMES
+                .iter()
+                .zip(last.events)
+                .map(|(name, n)| format!("{} {}", name, n))
+                .collect::<Vec<_>>()
+                .join("  "),
+        );
+
+        let font = FontId::monospace(10.0);
+        let lineheight = 12.0;
+        let graphsize = Vec2::new(GRAPHFRAMES as f32 * 2.0, 40.0);
+        let width = lines
+            .iter()
+            .map(|l| l.len() as f32 * 6.0)
+            .fold(graphsize.x, f32::max);
+        let size = Vec2::new(
+            width + 8.0,
+            lines.len() as f32 * lineheight + graphsize.y + 12.0,
+        );
+        let bg = Rect::from_min_size(pos2(screen.max.x - size.x, screen.min.y), size);
+        painter.rect_filled(bg, 0.0, Color32::from_black_alpha(180));
+        for (i, line) in lines.iter().enumerate() {
+            painter.text(
+                bg.min + Vec2::new(4.0, 4.0 + i as f32 * lineheight),
+                Align2::LEFT_TOP,
+                line,
+                font.clone(),
+                Color32::WHITE,
+            );
+        }
+
+        // bars scaled so the 60fps budget sits at half height
+        let graph = Rect::from_min_size(
+            pos2(bg.min.x + 4.0, bg.max.y - graphsize.y - 4.0),
+            graphsize,
+        );
+        let scale = graph.height() / (FRAMEBUDGET * 2.0);
+        let shown = self.frames.len().min(GRAPHFRAMES);
+        for (i, f) in self
+            .frames
+            .iter()
+            .skip(self.frames.len() - shown)
+            .enumerate()
+        {
+            let h = (f.work() * scale).min(graph.height());
+            let x = graph.min.x + i as f32 * 2.0;
+            let color = if f.work() > FRAMEBUDGET {
+                Color32::RED
+            } else {
+                Color32::GREEN
+            };
+            painter.rect_filled(
+                Rect::from_min_max(pos2(x, graph.max.y - h), pos2(x + 2.0, graph.max.y)),
+                0.0,
+                color,
+            );
+        }
+        let budget = graph.max.y - FRAMEBUDGET * scale;
+        painter.line_segment(
+            [pos2(graph.min.x, budget), pos2(graph.max.x, budget)],
+            Stroke::new(1.0, Color32::YELLOW),
+        );
+    }
+
+    /// the kept frames in chrome's trace event format, open with chrome://tracing or perfetto
+    pub fn chrome_trace(&self) -> serde_json::Value {
+        let mut events = vec![];
+        let span = |span: &Span, cat: &str, tid: u32| {
+            serde_json::json!({
+                "name": span.name,
+                "cat": cat,
+                "ph": "X",
+                "ts": span.start,
+                "dur": span.duration,
+                "pid": 1,
+                "tid": tid,
+            })
+        };
+        for f in &self.frames {
+            let counts: serde_json::Map<String, serde_json::Value> = EVENTNAMES
+                .iter()
+                .zip(f.events)
+                .map(|(name, n)| (name.to_string(), n.into()))
+                .collect();
+            events.push(serde_json::json!({
+                "name": format!("frame {}", f.number),
+                "cat": "frame",
+                "ph": "X",
+                "ts": f.start(),
+                "dur": f.render.start + f.render.duration - f.start(),
+                "pid": 1,
+                "tid": 1,
+                "args": counts,
+            }));
+            for s in &f.spans {
+                events.push(span(s, "script", 1));
+            }
+            events.push(span(&f.render, "render", 1));
+            if f.upload > 0 {
+                // the ui doesn't share the game's clock, so it's only placed right after the render
+                let upload = Span {
+                    name: "upload".to_string(),
+                    start: f.render.start + f.render.duration,
+                    duration: f.upload,
+                };
+                events.push(span(&upload, "upload", 2));
+            }
+        }
+        serde_json::json!({
+            "traceEvents": events,
+            "displayTimeUnit": "ms",
+        })
+    }
+    pub fn save_trace(&self, path: &std::path::Path) -> Result<(), String> {
+        let json = serde_json::to_string(&self.chrome_trace()).map_err(|e| e.to_string())?;
+        std::fs::write(path, json).map_err(|e| format!("could not write {}: {}", path.display(), e))
+    }
+}
+
+/// asks where to save and writes the kept frames as a chrome trace
+#[cfg(feature = "editor")]
+pub fn export_dialog(profiler: &Profiler, output: &mut Vec<egui::RichText>) {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let path = match rfd::FileDialog::new()
+            .add_filter("json", &["json"])
+            .set_file_name("trace.json")
+            .save_file()
+        {
+            Some(p) => p,
+            None => return,
+        };
+        match profiler.save_trace(&path) {
+            Ok(()) => output.push(
+                egui::RichText::new(format!(
+                    "saved {} frames to {}",
+                    profiler.frames.len(),
+                    path.display()
+                ))
+                .color(Color32::GREEN),
+            ),
+            Err(e) => output.push(egui::RichText::new(e).color(Color32::RED)),
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = profiler;
+        output
+            .push(egui::RichText::new("saving traces is not supported on web").color(Color32::RED));
+    }
+}
@@ -1,18 +1,229 @@
-use crate::zvm::{self, ZEvent, ZVMState, ZVM};
+use crate::app::draw_sprite;
+use crate::cart::{Property, PropertyValue, Sprite, LAYERS};
 use crate::FanzApp;
-// use egui::{
-//     pos2, vec2, Align2, Color32, Id, Key, LayerId, Painter, Rect, RichText, Sense, Stroke, Vec2,
-//     Widget,
-// };
-pub struct PropertiesWindow {}
-impl Default for PropertiesWindow {
-    fn default() -> Self {
-        PropertiesWindow {}
-    }
+use egui::{vec2, Color32, Pos2, Sense, Vec2};
+
+/// inspector for the selected object
+#[derive(Default)]
+pub struct PropertiesWindow {
+    pub enabled: bool,
+    /// text of the "add tag" field
+    pub newtag: String,
 }
 impl PropertiesWindow {
     pub fn ui<'a>(&mut self, app: &mut FanzApp<'a>, ui: &mut egui::Ui) {
-        // let mut object = app.cart.objects[]
-        // ui.heading()
+        // objects in the same scene that aren't under this one, and where they are
+        let (world, parents) = match app.cart.objects.get(app.selectedobject) {
+            Some(o) => {
+                let under = app.cart.subtree(app.selectedobject);
+                let parents: Vec<(usize, String, Pos2)> = (0..app.cart.objects.len())
+                    .filter(|i| app.cart.objects[*i].scene == o.scene && !under.contains(i))
+                    .map(|i| (i, app.cart.objects[i].name.clone(), app.cart.worldpos(i)))
+                    .collect();
+                (app.cart.worldpos(app.selectedobject), parents)
+            }
+            None => (Pos2::ZERO, vec![]),
+        };
+        let sprites = &app.cart.sprites;
+        let scenes = &app.cart.scenes;
+        let selectedsprite = app.selectedsprite;
+        let obj = match app.cart.objects.get_mut(app.selectedobject) {
+            Some(o) => o,
+            None => {
+                ui.label("no object selected");
+                return;
+            }
+        };
+        egui::Grid::new("properties_grid")
+            .num_columns(2)
+            .show(ui, |ui| {
+                ui.label("name");
+                ui.text_edit_singleline(&mut obj.name);
+                ui.end_row();
+
+                ui.label("enabled");
+                ui.checkbox(&mut obj.enabled, "");
+                ui.end_row();
+
+                ui.label("scene");
+                egui::ComboBox::from_id_source("scene")
+                    .selected_text(scenes.get(obj.scene).map_or("?", |s| s.name.as_str()))
+                    .show_ui(ui, |ui| {
+                        for (i, scene) in scenes.iter().enumerate() {
+                            ui.selectable_value(&mut obj.scene, i, &scene.name);
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("parent");
+                let current = obj
+                    .parent
+                    .and_then(|p| parents.iter().find(|(i, _, _)| *i == p));
+                let mut parent = current.map(|(i, _, _)| *i);
+                egui::ComboBox::from_id_source("parent")
+                    .selected_text(current.map_or("none", |(_, name, _)| name.as_str()))
+                    .show_ui(ui, |ui| {
+                        ui.selectable_value(&mut parent, None, "none");
+                        for (i, name, _) in &parents {
+                            ui.selectable_value(&mut parent, Some(*i), name);
+                        }
+                    });
+                if parent != current.map(|(i, _, _)| *i) {
+                    // stays where it is in the scene
+                    let parentpos = parent
+                        .and_then(|p| parents.iter().find(|(i, _, _)| *i == p))
+                        .map_or(Vec2::ZERO, |(_, _, pos)| pos.to_vec2());
+                    obj.pos = world - parentpos;
+                    obj.parent = parent;
+                }
+                ui.end_row();
+
+                ui.label("prototype");
+                ui.checkbox(&mut obj.prototype, "")
+                    .on_hover_text("only copies made with spawn(name, x, y) run");
+                ui.end_row();
+
+                ui.label("position").on_hover_text("relative to the parent");
+                ui.horizontal(|ui| {
+                    ui.add(egui::DragValue::new(&mut obj.pos.x).prefix("x "));
+                    ui.add(egui::DragValue::new(&mut obj.pos.y).prefix("y "));
+                });
+                ui.end_row();
+
+                ui.label("layer");
+                egui::ComboBox::from_id_source("drawlayer")
+                    .selected_text(LAYERS.get(obj.drawlayer).copied().unwrap_or("?"))
+                    .show_ui(ui, |ui| {
+                        for (i, name) in LAYERS.iter().enumerate() {
+                            ui.selectable_value(&mut obj.drawlayer, i, *name);
+                        }
+                    });
+                ui.end_row();
+
+                ui.label("depth");
+                ui.add(egui::DragValue::new(&mut obj.depth))
+                    .on_hover_text("on the same layer, higher depths draw on top");
+                ui.end_row();
+
+                ui.label("sprite");
+                ui.horizontal(|ui| {
+                    let mut hassprite = obj.sprite.is_some();
+                    if ui.checkbox(&mut hassprite, "").changed() {
+                        obj.sprite = if hassprite {
+                            Some(selectedsprite)
+                        } else {
+                            None
+                        };
+                    }
+                    if let Some(sprite) = &mut obj.sprite {
+                        sprite_ui(ui, sprite, sprites, selectedsprite);
+                    }
+                });
+                ui.end_row();
+
+                ui.label("tags");
+                ui.horizontal_wrapped(|ui| {
+                    let mut remove = None;
+                    for (i, tag) in obj.tags.iter().enumerate() {
+                        if ui.button(format!("{} x", tag)).clicked() {
+                            remove = Some(i);
+                        }
+                    }
+                    if let Some(i) = remove {
+                        obj.tags.remove(i);
+                    }
+                    let resp = ui.add(
+                        egui::TextEdit::singleline(&mut self.newtag)
+                            .hint_text("add tag")
+                            .desired_width(60.0),
+                    );
+                    if resp.lost_focus() && !self.newtag.trim().is_empty() {
+                        let tag = self.newtag.trim().to_string();
+                        if !obj.tags.contains(&tag) {
+                            obj.tags.push(tag);
+                        }
+                        self.newtag.clear();
+                    }
+                });
+                ui.end_row();
+            });
+
+        ui.separator();
+        ui.label("exported properties, set as variables before the script starts");
+        let mut remove = None;
+        egui::Grid::new("exported_grid")
+            .num_columns(4)
+            .show(ui, |ui| {
+                for (i, prop) in obj.properties.iter_mut().enumerate() {
+                    ui.add(egui::TextEdit::singleline(&mut prop.name).desired_width(80.0));
+                    egui::ComboBox::from_id_source(("property_type", i))
+                        .selected_text(typename(&prop.value))
+                        .show_ui(ui, |ui| {
+                            for value in [
+                                PropertyValue::Number(0.0),
+                                PropertyValue::String(String::new()),
+                                PropertyValue::Color(Color32::WHITE),
+                                PropertyValue::Sprite(0),
+                            ] {
+                                let name = typename(&value);
+                                let current = typename(&prop.value) == name;
+                                if ui.selectable_label(current, name).clicked() && !current {
+                                    prop.value = value;
+                                }
+                            }
+                        });
+                    match &mut prop.value {
+                        PropertyValue::Number(n) => {
+                            ui.add(egui::DragValue::new(n).speed(0.1));
+                        }
+                        PropertyValue::String(s) => {
+                            ui.add(egui::TextEdit::singleline(s).desired_width(80.0));
+                        }
+                        PropertyValue::Color(c) => {
+                            ui.color_edit_button_srgba(c);
+                        }
+                        PropertyValue::Sprite(sprite) => {
+                            ui.horizontal(|ui| sprite_ui(ui, sprite, sprites, selectedsprite));
+                        }
+                    }
+                    if ui.button("x").clicked() {
+                        remove = Some(i);
+                    }
+                    ui.end_row();
+                }
+            });
+        if let Some(i) = remove {
+            obj.properties.remove(i);
+        }
+        if ui.button("add property").clicked() {
+            obj.properties.push(Property {
+                name: format!("prop{}", obj.properties.len()),
+                value: PropertyValue::Number(0.0),
+            });
+        }
+    }
+}
+
+fn typename(value: &PropertyValue) -> &'static str {
+    match value {
+        PropertyValue::Number(_) => "number",
+        PropertyValue::String(_) => "string",
+        PropertyValue::Color(_) => "color",
+        PropertyValue::Sprite(_) => "sprite",
+    }
+}
+
+/// sprite index with a preview. clicking the preview takes the sprite selected in the editor
+fn sprite_ui(ui: &mut egui::Ui, sprite: &mut usize, sprites: &[Sprite], selected: usize) {
+    ui.add(egui::DragValue::new(sprite).clamp_range(0..=sprites.len().saturating_sub(1)));
+    let (resp, painter) = ui.allocate_painter(vec2(16.0, 16.0), Sense::click());
+    if let Some(spr) = sprites.get(*sprite) {
+        draw_sprite(&painter, resp.rect, spr);
+    }
+    if resp
+        .on_hover_text("click to use the selected sprite")
+        .clicked()
+    {
+        *sprite = selected;
     }
 }
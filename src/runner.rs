@@ -0,0 +1,778 @@
+use crate::cart::{Cart, EditorObject, PropertyValue, LAYERS, MAPLAYER, TILESIZE};
+use crate::collision::Body;
+use crate::consolebuiltins::ZColor;
+use crate::debugger::{self, BreakHook, BreakInfo, DebugCommand};
+use crate::profiler::{FrameProfile, Span};
+use crate::zvm::{self, ZEvent, ZVM};
+use egui::{Color32, Pos2};
+use instant::Instant;
+use std::collections::{BTreeSet, HashMap};
+use zsp_core::exceptions::Exception;
+use zsp_core::runtime::{DynObjectContainer, Value};
+
+/// what the ui sends to the game
+#[derive(Debug)]
+pub enum GameCommand {
+    Input {
+        keys: Vec<String>,
+        mouse: Option<(f32, f32)>,
+    },
+    Pause,
+    Resume,
+    /// run this many frames, then pause again
+    Step(u32),
+    /// new scripts for every object, in cart order. unchanged ones are skipped
+    Reload {
+        /// the objects' names, to tell if they're still the ones the game started with
+        names: Vec<String>,
+        scripts: Vec<String>,
+    },
+    /// answer to a `GameMessage::Break`
+    Debug(DebugCommand),
+    /// breakpoint lines for every object, in cart order
+    Breakpoints(Vec<BTreeSet<usize>>),
+    /// turns `GameMessage::Watch` on or off
+    Watch(bool),
+    /// which layers get drawn, see `cart::LAYERS`
+    Layers([bool; LAYERS.len()]),
+    /// overwrites a top level variable of the object with this id
+    SetVariable {
+        object: u64,
+        name: String,
+        value: WatchValue,
+    },
+}
+/// what the game sends back to the ui
+#[derive(Debug)]
+pub enum GameMessage {
+    Frame(Frame),
+    Put(String),
+    Error(String),
+    /// name of an object whose script was swapped in
+    Reloaded(String),
+    /// the game stopped at a line and waits for a `GameCommand::Debug`
+    Break(BreakInfo),
+    /// where the time of a frame went, sent right before the frame
+    Profile(FrameProfile),
+    /// every object and its top level variables, after every frame while watching
+    Watch(Vec<WatchObject>),
+    /// a script blew its budget, the game stopped itself
+    Paused,
+    /// the game is gone, either it failed to start or the thread died
+    Exited,
+}
+/// a finished frame, `width` * `height` premultiplied pixels row by row
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// how many frames the game has run, counting this one
+    pub number: u64,
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchObject {
+    pub id: u64,
+    pub name: String,
+    /// sorted by name
+    pub variables: Vec<(String, WatchValue)>,
+}
+/// a copy of a zsp value that can leave the game thread
+#[derive(Debug, Clone, PartialEq)]
+pub enum WatchValue {
+    Number(f32),
+    String(String),
+    Bool(bool),
+    /// anything else, can't be edited
+    Other(String),
+}
+impl WatchValue {
+    pub fn from_value(value: &Value<'_>) -> WatchValue {
+        match value {
+            Value::Number(n) => WatchValue::Number(*n),
+            Value::String(s) => WatchValue::String(s.clone()),
+            Value::Bool(b) => WatchValue::Bool(*b),
+            other => WatchValue::Other(other.to_string()),
+        }
+    }
+    pub fn to_value<'a>(&self) -> Option<Value<'a>> {
+        match self {
+            WatchValue::Number(n) => Some(Value::Number(*n)),
+            WatchValue::String(s) => Some(Value::String(s.clone())),
+            WatchValue::Bool(b) => Some(Value::Bool(*b)),
+            WatchValue::Other(_) => None,
+        }
+    }
+}
+
+/// objects that can exist at once, so a runaway `spawn` can't take all the memory
+pub const MAXOBJECTS: usize = 4096;
+
+pub struct Game {
+    /// in the order they run and draw
+    pub objects: Vec<GameObject>,
+}
+pub struct GameObject {
+    pub vm: ZVM,
+    /// which `EditorObject` this came from. spawned copies share it
+    pub index: usize,
+    /// starts at `EditorObject::pos`, scripts move it with `setpos`
+    pub pos: Pos2,
+    /// what `spawn` returns and `destroy` takes
+    pub id: u64,
+}
+impl Game {
+    /// lower depths run and draw first, on the same depth older objects go first
+    pub fn insert(&mut self, cart: &Cart, obj: GameObject) {
+        let depth = cart.objects[obj.index].depth;
+        let at = self
+            .objects
+            .iter()
+            .position(|o| cart.objects[o.index].depth > depth)
+            .unwrap_or(self.objects.len());
+        self.objects.insert(at, obj);
+    }
+}
+
+/// owns a running game and turns its draw events into frames.
+/// lives on whatever thread runs the game, since the vms can't leave it
+pub struct Runner {
+    pub cart: Cart,
+    pub game: Game,
+    pub paused: bool,
+    /// frames left to run while paused, see `GameCommand::Step`
+    pub steps: u32,
+    pub frames: u64,
+    pub watching: bool,
+    /// profile timestamps count from here
+    pub started: Instant,
+    /// scripts are instrumented for the debugger, spawned ones too
+    pub debug: bool,
+    /// layers that get drawn
+    pub visible: [bool; LAYERS.len()],
+    /// index of the scene that's running
+    pub scene: usize,
+}
+impl Runner {
+    /// resets this thread's vm state and starts a fresh game from the cart.
+    /// with a hook the scripts are instrumented so the game can stop at breakpoints
+    pub fn start(cart: Cart, hook: Option<BreakHook>) -> Result<Runner, String> {
+        zvm::initstate(cart.budget);
+        let debug = hook.is_some();
+        zvm::with_state(|state| {
+            state.debug.hook = hook;
+            state.debug.breakpoints = cart.objects.iter().map(|o| o.breakpoints.clone()).collect();
+            state.names = cart.objects.iter().map(|o| o.name.clone()).collect();
+            state.scenes = cart.scenes.iter().map(|s| s.name.clone()).collect();
+            state.tags = cart.objects.iter().map(|o| o.tags.clone()).collect();
+            state.sprites = cart.sprites.clone();
+            state.animations = cart.animations.clone();
+            state.map = cart.scenes[0].map.clone();
+        });
+        let game = Runner::startgame(&cart, 0, debug)?;
+        Ok(Runner {
+            cart,
+            game,
+            paused: false,
+            steps: 0,
+            frames: 0,
+            watching: false,
+            started: Instant::now(),
+            debug,
+            visible: [true; LAYERS.len()],
+            scene: 0,
+        })
+    }
+    /// starts the objects of one scene
+    pub fn startgame(cart: &Cart, scene: usize, debug: bool) -> Result<Game, String> {
+        let mut game = Game { objects: vec![] };
+        // prototypes only show up when a script spawns them
+        for (i, editorobj) in cart.objects.iter().enumerate() {
+            if editorobj.scene != scene || !editorobj.enabled || editorobj.prototype {
+                continue;
+            }
+            let id = zvm::with_state(|s| s.newid());
+            match Runner::startobject(cart, i, cart.worldpos(i), id, debug) {
+                Ok(obj) => game.insert(cart, obj),
+                Err(e) => return Err(zvm::errfmt(e, &editorobj.script)),
+            }
+        }
+        Ok(game)
+    }
+    /// runs the top level of a new copy of `cart.objects[index]`
+    fn startobject(
+        cart: &Cart,
+        index: usize,
+        pos: Pos2,
+        id: u64,
+        debug: bool,
+    ) -> Result<GameObject, Exception> {
+        let editorobj = &cart.objects[index];
+        zvm::with_state(|s| s.begin(index, editorobj, debugger::TOPLEVEL, pos, id));
+        let vars = properties(editorobj);
+        let vm = if debug {
+            ZVM::start_debug(editorobj.script.clone(), vars)?
+        } else {
+            ZVM::start(editorobj.script.clone(), vars)?
+        };
+        Ok(GameObject {
+            vm,
+            index,
+            pos: zvm::with_state(|s| s.pos),
+            id,
+        })
+    }
+    /// makes what scripts spawned, delivers what they sent, then removes what they destroyed
+    fn spawned(&mut self, out: &mut Vec<GameMessage>) {
+        let spawns = zvm::with_state(|s| std::mem::take(&mut s.spawns));
+        for spawn in spawns {
+            let editorobj = &self.cart.objects[spawn.object];
+            if self.game.objects.len() >= MAXOBJECTS {
+                out.push(GameMessage::Error(format!(
+                    "could not spawn {}, there are already {} objects",
+                    editorobj.name, MAXOBJECTS
+                )));
+                continue;
+            }
+            match Runner::startobject(&self.cart, spawn.object, spawn.pos, spawn.id, self.debug) {
+                Ok(obj) => self.game.insert(&self.cart, obj),
+                Err(e) => out.push(GameMessage::Error(zvm::errfmt(e, &editorobj.script))),
+            }
+        }
+        self.deliver(out);
+        // after spawning, so something spawned and destroyed in the same frame stays gone
+        let destroys = zvm::with_state(|s| std::mem::take(&mut s.destroys));
+        if !destroys.is_empty() {
+            self.game.objects.retain(|o| !destroys.contains(&o.id));
+        }
+        if let Some(scene) = zvm::with_state(|s| s.goto.take()) {
+            self.gotoscene(scene, out);
+        }
+    }
+    /// throws away every object and starts the scene's. globals stay
+    fn gotoscene(&mut self, scene: usize, out: &mut Vec<GameMessage>) {
+        zvm::with_state(|state| {
+            state.spawns.clear();
+            state.destroys.clear();
+            state.messages.clear();
+            state.map = self.cart.scenes[scene].map.clone();
+        });
+        self.game.objects.clear();
+        self.scene = scene;
+        match Runner::startgame(&self.cart, scene, self.debug) {
+            Ok(game) => self.game = game,
+            Err(e) => {
+                out.push(GameMessage::Error(e));
+                self.paused = true;
+                self.steps = 0;
+                out.push(GameMessage::Paused);
+            }
+        }
+    }
+    /// calls `on_message` of every target. whatever gets sent from there arrives next frame
+    fn deliver(&mut self, out: &mut Vec<GameMessage>) {
+        let messages = zvm::with_state(|s| std::mem::take(&mut s.messages));
+        for message in messages {
+            for obj in &mut self.game.objects {
+                let editorobj = &self.cart.objects[obj.index];
+                let target = zvm::with_state(|s| {
+                    if !s.targets(&message.target, obj.index, obj.id) {
+                        return false;
+                    }
+                    s.begin(obj.index, editorobj, debugger::MESSAGE, obj.pos, obj.id);
+                    true
+                });
+                if !target {
+                    continue;
+                }
+                let args = vec![
+                    Value::String(message.name.clone()),
+                    message.value.to_value().unwrap_or(Value::Null),
+                    Value::Number(message.from as f32),
+                ];
+                let result = obj.vm.call(debugger::MESSAGE, args);
+                obj.pos = zvm::with_state(|s| s.pos);
+                if let Err(e) = result {
+                    out.push(GameMessage::Error(obj.vm.fmt(e)));
+                }
+            }
+        }
+    }
+    pub fn command(&mut self, cmd: GameCommand, out: &mut Vec<GameMessage>) {
+        match cmd {
+            GameCommand::Input { keys, mouse } => zvm::with_state(|state| {
+                state.keys = keys;
+                state.mouse = mouse;
+            }),
+            GameCommand::Pause => self.paused = true,
+            GameCommand::Resume => {
+                self.paused = false;
+                self.steps = 0;
+            }
+            GameCommand::Step(n) => {
+                self.paused = true;
+                self.steps += n;
+            }
+            GameCommand::Reload { names, scripts } => self.reload(names, scripts, out),
+            GameCommand::Breakpoints(b) => zvm::with_state(|s| s.debug.breakpoints = b),
+            GameCommand::Layers(visible) => self.visible = visible,
+            // only means something while stopped at a breakpoint, see `debugger::hit`
+            GameCommand::Debug(_) => (),
+            GameCommand::Watch(watching) => {
+                self.watching = watching;
+                self.watch(out);
+            }
+            GameCommand::SetVariable {
+                object,
+                name,
+                value,
+            } => {
+                let obj = match self.game.objects.iter().find(|o| o.id == object) {
+                    Some(o) => o,
+                    None => return,
+                };
+                let scope = obj.vm.root_scope.borrow();
+                if let (Some(var), Some(value)) = (scope.variables.get(&name), value.to_value()) {
+                    *var.borrow_mut() = value;
+                }
+                drop(scope);
+                self.watch(out);
+            }
+        }
+    }
+    pub fn reload(&mut self, names: Vec<String>, scripts: Vec<String>, out: &mut Vec<GameMessage>) {
+        let same = names.len() == self.cart.objects.len()
+            && names
+                .iter()
+                .zip(&self.cart.objects)
+                .all(|(n, o)| *n == o.name);
+        if !same || scripts.len() != names.len() {
+            out.push(GameMessage::Error(
+                "objects were added, removed, renamed or moved, restart the game to pick them up"
+                    .to_string(),
+            ));
+            return;
+        }
+        for (index, script) in scripts.into_iter().enumerate() {
+            let editorobj = &mut self.cart.objects[index];
+            if editorobj.script == script {
+                continue;
+            }
+            // every running copy gets the new script, later spawns pick it up from the cart
+            let mut failed = false;
+            for obj in self.game.objects.iter_mut().filter(|o| o.index == index) {
+                zvm::with_state(|s| s.begin(index, editorobj, debugger::TOPLEVEL, obj.pos, obj.id));
+                let result = obj.vm.reload(script.clone(), properties(editorobj));
+                if let Err(e) = result {
+                    out.push(GameMessage::Error(zvm::errfmt(e, &script)));
+                    failed = true;
+                    break;
+                }
+            }
+            if !failed {
+                editorobj.script = script;
+                out.push(GameMessage::Reloaded(editorobj.name.clone()));
+            }
+        }
+    }
+    /// runs every object's `draw` once and renders what they drew
+    pub fn frame(&mut self, out: &mut Vec<GameMessage>) {
+        if self.paused {
+            if self.steps == 0 {
+                return;
+            }
+            self.steps -= 1;
+        }
+        self.frames += 1;
+        let started = self.started;
+        let since = |t: Instant| t.duration_since(started).as_micros() as u64;
+        let mut spans = vec![];
+
+        zvm::with_state(|state| {
+            state.clock = self.frames;
+            // the map goes under the objects' sprites, on its own layer
+            state.layer = MAPLAYER;
+            let map = &self.cart.scenes[self.scene].map;
+            for x in 0..map.num_rows() {
+                for y in 0..map.num_columns() {
+                    if let Some(sprite) = *map.get(x, y).unwrap() {
+                        state.push(ZEvent::Sprite {
+                            x: (x as u32 * TILESIZE) as f32,
+                            y: (y as u32 * TILESIZE) as f32,
+                            sprite,
+                        });
+                    }
+                }
+            }
+            // what collision checks see, each object moves in here right after it runs
+            state.bodies = self
+                .game
+                .objects
+                .iter()
+                .map(|o| Body {
+                    id: o.id,
+                    index: o.index,
+                    pos: o.pos,
+                    sprite: self.cart.objects[o.index].sprite,
+                })
+                .collect();
+        });
+        for (k, obj) in self.game.objects.iter_mut().enumerate() {
+            let mut timedout = false;
+            let editorobj = &self.cart.objects[obj.index];
+            let callstart = Instant::now();
+            zvm::with_state(|state| {
+                state.begin(obj.index, editorobj, debugger::DRAW, obj.pos, obj.id);
+                if let Some(sprite) = editorobj.sprite {
+                    state.push(ZEvent::Sprite {
+                        x: obj.pos.x,
+                        y: obj.pos.y,
+                        sprite,
+                    });
+                }
+            });
+            let result = obj.vm.draw();
+            obj.pos = zvm::with_state(|state| {
+                state.bodies[k].pos = state.pos;
+                state.pos
+            });
+            if let Err(e) = result {
+                let timeout = e.errtype == zvm::TIMEOUT_EXCEPTION;
+                out.push(GameMessage::Error(obj.vm.fmt(e)));
+                if timeout {
+                    self.paused = true;
+                    self.steps = 0;
+                    out.push(GameMessage::Paused);
+                    timedout = true;
+                }
+            }
+            spans.push(Span {
+                name: editorobj.name.clone(),
+                start: since(callstart),
+                duration: callstart.elapsed().as_micros() as u64,
+            });
+            // stepping also pauses, so only a timeout stops the rest of the frame
+            if timedout {
+                break;
+            }
+        }
+        self.spawned(out);
+        let renderstart = Instant::now();
+        let mut events = [0; 4];
+
+        let mut frame = Frame {
+            number: self.frames,
+            width: self.cart.width as usize,
+            height: self.cart.height as usize,
+            pixels: vec![Color32::BLACK; self.cart.width as usize * self.cart.height as usize],
+        };
+        // back to front. the sort is stable, so each layer keeps the order things were drawn in
+        let mut buffer = zvm::with_state(|s| std::mem::take(&mut s.buffer));
+        buffer.sort_by_key(|(layer, _)| *layer);
+        for (layer, i) in buffer {
+            events[match i {
+                ZEvent::GSet { .. } => 0,
+                ZEvent::Rect { .. } => 1,
+                ZEvent::Sprite { .. } => 2,
+                ZEvent::Put(_) => 3,
+            }] += 1;
+            let hidden = !self.visible.get(layer).copied().unwrap_or(true);
+            if hidden && !matches!(i, ZEvent::Put(_)) {
+                continue;
+            }
+            match i {
+                ZEvent::Put(s) => out.push(GameMessage::Put(s)),
+                ZEvent::GSet { x, y, color } => {
+                    frame.set(x.floor() as i64, y.floor() as i64, color)
+                }
+                ZEvent::Rect { x, y, h, w, color } => {
+                    let (x, y) = (x.floor() as i64, y.floor() as i64);
+                    for py in y..y + h.round() as i64 {
+                        for px in x..x + w.round() as i64 {
+                            frame.set(px, py, color);
+                        }
+                    }
+                }
+                ZEvent::Sprite {
+                    x: sx,
+                    y: sy,
+                    sprite,
+                } => {
+                    let spritedata = match self.cart.sprites.get(sprite) {
+                        Some(s) => &s.data,
+                        None => continue,
+                    };
+                    let (sx, sy) = (sx.floor() as i64, sy.floor() as i64);
+                    for x in 0..spritedata.num_rows() {
+                        for y in 0..spritedata.num_columns() {
+                            frame.set(sx + x as i64, sy + y as i64, *spritedata.get(x, y).unwrap());
+                        }
+                    }
+                }
+            }
+        }
+        out.push(GameMessage::Profile(FrameProfile {
+            number: self.frames,
+            spans,
+            render: Span {
+                name: "render".to_string(),
+                start: since(renderstart),
+                duration: renderstart.elapsed().as_micros() as u64,
+            },
+            events,
+            upload: 0,
+        }));
+        out.push(GameMessage::Frame(frame));
+        self.watch(out);
+    }
+    /// sends every object's top level variables, if the ui is watching
+    pub fn watch(&self, out: &mut Vec<GameMessage>) {
+        if !self.watching {
+            return;
+        }
+        let objects = self
+            .game
+            .objects
+            .iter()
+            .map(|obj| {
+                let scope = obj.vm.root_scope.borrow();
+                let mut variables: Vec<(String, WatchValue)> = scope
+                    .variables
+                    .iter()
+                    .map(|(name, value)| (name.clone(), WatchValue::from_value(&value.borrow())))
+                    .collect();
+                variables.sort_by(|a, b| a.0.cmp(&b.0));
+                WatchObject {
+                    id: obj.id,
+                    name: self.cart.objects[obj.index].name.clone(),
+                    variables,
+                }
+            })
+            .collect();
+        out.push(GameMessage::Watch(objects));
+    }
+}
+
+impl Frame {
+    /// draws `color` over the pixel at x, y. anything off screen is dropped
+    pub fn set(&mut self, x: i64, y: i64, color: Color32) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let under = &mut self.pixels[y as usize * self.width + x as usize];
+        // both premultiplied, so this is plain "over"
+        let inv = 255 - color.a() as u16;
+        let mix = |c: u8, u: u8| (c as u16 + u as u16 * inv / 255).min(255) as u8;
+        *under = Color32::from_rgba_premultiplied(
+            mix(color.r(), under.r()),
+            mix(color.g(), under.g()),
+            mix(color.b(), under.b()),
+            mix(color.a(), under.a()),
+        );
+    }
+}
+
+/// an object's exported properties, as variables for its script
+fn properties<'a>(editorobj: &EditorObject) -> HashMap<String, Value<'a>> {
+    editorobj
+        .properties
+        .iter()
+        .map(|p| {
+            let value = match &p.value {
+                PropertyValue::Number(n) => Value::Number(*n),
+                PropertyValue::String(s) => Value::String(s.clone()),
+                PropertyValue::Color(c) => Value::DynObject(DynObjectContainer {
+                    val: Box::new(ZColor::from_color(*c)),
+                }),
+                PropertyValue::Sprite(i) => Value::Number(*i as f32),
+            };
+            (p.name.clone(), value)
+        })
+        .collect()
+}
+
+/// the ui's end of a running game.
+/// natively the game gets its own thread, so a slow script only slows the game down, not the editor
+const FRAMETIME: std::time::Duration = std::time::Duration::from_micros(16_667);
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct GameHandle {
+    commands: std::sync::mpsc::Sender<GameCommand>,
+    messages: std::sync::mpsc::Receiver<GameMessage>,
+}
+/// messages the ui can fall behind by. past that frames, profiles and watches get dropped,
+/// and anything else waits for the ui
+#[cfg(not(target_arch = "wasm32"))]
+const BACKLOG: usize = 256;
+#[cfg(not(target_arch = "wasm32"))]
+impl GameHandle {
+    /// `debug` instruments the scripts, so the game can stop at breakpoints
+    pub fn spawn(cart: Cart, debug: bool) -> GameHandle {
+        use std::sync::mpsc::{channel, sync_channel};
+
+        let (commands, commandrx) = channel();
+        let (messagetx, messages) = sync_channel(BACKLOG);
+        let spawned = std::thread::Builder::new()
+            .name("fan-z game".to_string())
+            .spawn(move || {
+                GameHandle::run(cart, debug, commandrx, messagetx);
+                zvm::freestate();
+            });
+        if let Err(e) = spawned {
+            let (tx, rx) = channel();
+            let _ = tx.send(GameMessage::Error(format!(
+                "could not start the game thread: {}",
+                e
+            )));
+            let _ = tx.send(GameMessage::Exited);
+            return GameHandle {
+                commands,
+                messages: rx,
+            };
+        }
+        GameHandle { commands, messages }
+    }
+    /// the game thread, returns once the handle is dropped or the game can't start
+    fn run(
+        cart: Cart,
+        debug: bool,
+        commandrx: std::sync::mpsc::Receiver<GameCommand>,
+        messagetx: std::sync::mpsc::SyncSender<GameMessage>,
+    ) {
+        use std::rc::Rc;
+        use std::sync::mpsc::{TryRecvError, TrySendError};
+
+        let commandrx = Rc::new(commandrx);
+        let hook: Option<BreakHook> = if debug {
+            let commandrx = commandrx.clone();
+            let messagetx = messagetx.clone();
+            // blocks the game thread until the ui says how to go on
+            Some(Box::new(move |info: &BreakInfo| {
+                messagetx.send(GameMessage::Break(info.clone())).ok()?;
+                commandrx.recv().ok()
+            }))
+        } else {
+            None
+        };
+        let mut runner = match Runner::start(cart, hook) {
+            Ok(r) => r,
+            Err(e) => {
+                let _ = messagetx.send(GameMessage::Error(e));
+                let _ = messagetx.send(GameMessage::Exited);
+                return;
+            }
+        };
+        let mut out = vec![];
+        loop {
+            let framestart = Instant::now();
+            // sent while the game was stopped at a breakpoint, they came before anything still waiting
+            for cmd in zvm::with_state(|s| std::mem::take(&mut s.debug.queued)) {
+                runner.command(cmd, &mut out);
+            }
+            loop {
+                match commandrx.try_recv() {
+                    Ok(cmd) => runner.command(cmd, &mut out),
+                    Err(TryRecvError::Empty) => break,
+                    // the handle was dropped, the game is stopped
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            }
+            runner.frame(&mut out);
+            for msg in out.drain(..) {
+                let hungup = match msg {
+                    // a newer one comes next frame
+                    GameMessage::Frame(_) | GameMessage::Profile(_) | GameMessage::Watch(_) => {
+                        matches!(messagetx.try_send(msg), Err(TrySendError::Disconnected(_)))
+                    }
+                    msg => messagetx.send(msg).is_err(),
+                };
+                if hungup {
+                    return;
+                }
+            }
+            if let Some(rest) = FRAMETIME.checked_sub(framestart.elapsed()) {
+                std::thread::sleep(rest);
+            }
+        }
+    }
+    pub fn send(&mut self, cmd: GameCommand) {
+        let _ = self.commands.send(cmd);
+    }
+    /// everything the game sent since the last poll
+    pub fn poll(&mut self) -> Vec<GameMessage> {
+        use std::sync::mpsc::TryRecvError;
+        let mut out = vec![];
+        loop {
+            match self.messages.try_recv() {
+                Ok(msg) => out.push(msg),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    // the thread is gone without saying goodbye, it panicked
+                    if !matches!(out.last(), Some(GameMessage::Exited)) {
+                        out.push(GameMessage::Exited);
+                    }
+                    break;
+                }
+            }
+        }
+        out
+    }
+}
+
+/// no threads on the web, the game catches up on its frames whenever the ui polls it
+#[cfg(target_arch = "wasm32")]
+pub struct GameHandle {
+    runner: Option<Runner>,
+    out: Vec<GameMessage>,
+    /// game time that hasn't been run yet
+    behind: std::time::Duration,
+    lastpoll: Instant,
+}
+/// frames a single poll may run, so a stall doesn't turn into a burst of catching up
+#[cfg(target_arch = "wasm32")]
+const MAXCATCHUP: u32 = 4;
+#[cfg(target_arch = "wasm32")]
+impl GameHandle {
+    /// there's no thread to block on the web, so the debugger can't stop the game
+    pub fn spawn(cart: Cart, _debug: bool) -> GameHandle {
+        match Runner::start(cart, None) {
+            Ok(r) => GameHandle {
+                runner: Some(r),
+                out: vec![],
+                behind: std::time::Duration::ZERO,
+                lastpoll: Instant::now(),
+            },
+            Err(e) => GameHandle {
+                runner: None,
+                out: vec![GameMessage::Error(e), GameMessage::Exited],
+                behind: std::time::Duration::ZERO,
+                lastpoll: Instant::now(),
+            },
+        }
+    }
+    pub fn send(&mut self, cmd: GameCommand) {
+        if let Some(runner) = &mut self.runner {
+            runner.command(cmd, &mut self.out);
+        }
+    }
+    /// runs as many frames as fit in the time since the last poll, so the game keeps
+    /// its speed whatever the display's refresh rate is
+    pub fn poll(&mut self) -> Vec<GameMessage> {
+        if let Some(runner) = &mut self.runner {
+            self.behind += self.lastpoll.elapsed();
+            self.lastpoll = Instant::now();
+            let mut frames = 0;
+            while self.behind >= FRAMETIME {
+                if frames == MAXCATCHUP {
+                    self.behind = std::time::Duration::ZERO;
+                    break;
+                }
+                runner.frame(&mut self.out);
+                self.behind -= FRAMETIME;
+                frames += 1;
+            }
+        }
+        std::mem::take(&mut self.out)
+    }
+}
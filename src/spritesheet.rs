@@ -0,0 +1,159 @@
+use crate::cart::Sprite;
+use crate::FanzApp;
+use array2d::Array2D;
+use egui::{Color32, RichText};
+use image::{Rgba, RgbaImage};
+
+/// packs every sprite into one sheet, `columns` sprites per row.
+/// cells are as big as the largest sprite, smaller sprites sit in the top left of their cell
+pub fn sprites_to_image(sprites: &[Sprite], columns: usize) -> RgbaImage {
+    let columns = columns.max(1);
+    let cellw = sprites.iter().map(|s| s.data.num_rows()).max().unwrap_or(8);
+    let cellh = sprites
+        .iter()
+        .map(|s| s.data.num_columns())
+        .max()
+        .unwrap_or(8);
+    let sheetrows = ((sprites.len() + columns - 1) / columns).max(1);
+
+    let mut img = RgbaImage::new((cellw * columns) as u32, (cellh * sheetrows) as u32);
+    for (i, spr) in sprites.iter().enumerate() {
+        let cellx = (i % columns) * cellw;
+        let celly = (i / columns) * cellh;
+        for x in 0..spr.data.num_rows() {
+            for y in 0..spr.data.num_columns() {
+                let color = spr.data.get(x, y).unwrap();
+                img.put_pixel(
+                    (cellx + x) as u32,
+                    (celly + y) as u32,
+                    Rgba(color.to_srgba_unmultiplied()),
+                );
+            }
+        }
+    }
+    img
+}
+
+/// slices an image into `size`x`size` sprites, left to right, top to bottom.
+/// leftover pixels on the right and bottom edges are ignored
+pub fn image_to_sprites(img: &RgbaImage, size: usize, palette: Option<&[Color32]>) -> Vec<Sprite> {
+    let size = size.max(1);
+    let mut sprites = vec![];
+    for celly in 0..img.height() as usize / size {
+        for cellx in 0..img.width() as usize / size {
+            let mut data = Array2D::filled_with(Color32::TRANSPARENT, size, size);
+            for x in 0..size {
+                for y in 0..size {
+                    let [r, g, b, a] = img
+                        .get_pixel((cellx * size + x) as u32, (celly * size + y) as u32)
+                        .0;
+                    let color = Color32::from_rgba_unmultiplied(r, g, b, a);
+                    *data.get_mut(x, y).unwrap() = match palette {
+                        Some(palette) => quantize(color, palette),
+                        None => color,
+                    };
+                }
+            }
+            sprites.push(Sprite { data, flags: 0 });
+        }
+    }
+    sprites
+}
+
+/// snaps a color to the closest palette entry. mostly transparent pixels become fully transparent
+pub fn quantize(color: Color32, palette: &[Color32]) -> Color32 {
+    if color.a() < 128 {
+        return Color32::TRANSPARENT;
+    }
+    if palette.is_empty() {
+        return color;
+    }
+    let [r, g, b, _] = color.to_srgba_unmultiplied();
+    *palette
+        .iter()
+        .min_by_key(|p| {
+            let [pr, pg, pb, _] = p.to_srgba_unmultiplied();
+            let dr = pr as i32 - r as i32;
+            let dg = pg as i32 - g as i32;
+            let db = pb as i32 - b as i32;
+            dr * dr + dg * dg + db * db
+        })
+        .unwrap()
+}
+
+pub fn load_png(path: &std::path::Path) -> Result<RgbaImage, String> {
+    match image::open(path) {
+        Ok(img) => Ok(img.into_rgba8()),
+        Err(e) => Err(format!("could not read {}: {}", path.display(), e)),
+    }
+}
+pub fn save_png(path: &std::path::Path, img: &RgbaImage) -> Result<(), String> {
+    img.save_with_format(path, image::ImageFormat::Png)
+        .map_err(|e| format!("could not write {}: {}", path.display(), e))
+}
+
+/// asks for a png and appends the sliced sprites to the cart
+pub fn import_dialog(app: &mut FanzApp<'_>, size: usize, quantized: bool) {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let path = match rfd::FileDialog::new()
+            .add_filter("png", &["png"])
+            .pick_file()
+        {
+            Some(p) => p,
+            None => return,
+        };
+        match load_png(&path) {
+            Ok(img) => {
+                let palette = app.cart.palette.clone();
+                let sprites =
+                    image_to_sprites(&img, size, if quantized { Some(&palette) } else { None });
+                app.output.push(
+                    RichText::new(format!(
+                        "imported {} sprites from {}",
+                        sprites.len(),
+                        path.display()
+                    ))
+                    .color(Color32::GREEN),
+                );
+                app.cart.sprites.extend(sprites);
+            }
+            Err(e) => app.output.push(RichText::new(e).color(Color32::RED)),
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = (size, quantized);
+        app.output
+            .push(RichText::new("png import is not supported on web").color(Color32::RED));
+    }
+}
+
+/// asks where to save and writes every sprite as one packed sheet
+pub fn export_dialog(app: &mut FanzApp<'_>, columns: usize) {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let path = match rfd::FileDialog::new()
+            .add_filter("png", &["png"])
+            .set_file_name("sprites.png")
+            .save_file()
+        {
+            Some(p) => p,
+            None => return,
+        };
+        let img = sprites_to_image(&app.cart.sprites, columns);
+        match save_png(&path, &img) {
+            Ok(()) => app.output.push(
+                RichText::new(format!("exported sprite sheet to {}", path.display()))
+                    .color(Color32::GREEN),
+            ),
+            Err(e) => app.output.push(RichText::new(e).color(Color32::RED)),
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = columns;
+        app.output
+            .push(RichText::new("png export is not supported on web").color(Color32::RED));
+    }
+}
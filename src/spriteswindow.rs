@@ -1,5 +1,8 @@
+use crate::app::draw_sprite;
 use crate::app::toolbtn_ui;
+use crate::cart::{Animation, AnimationFrame, LoopMode, Sprite, FLAGCOLORS, FLAGNAMES};
 use crate::mapwindow::Resize;
+use crate::spritesheet;
 use crate::viewport::Viewport;
 use crate::zvm::{self, ZEvent, ZVMState, ZVM};
 use crate::FanzApp;
@@ -20,6 +23,14 @@ pub struct SpritesWindow {
     selectedcolor: Color32,
     #[serde(skip)]
     selectedtool: Tool,
+
+    pub importsize: usize,
+    pub quantize: bool,
+    pub sheetcolumns: usize,
+
+    /// index into `Cart::animations` of the one in the timeline
+    #[serde(skip)]
+    selectedanimation: usize,
 }
 #[derive(PartialEq, Eq, Debug)]
 pub enum Tool {
@@ -42,6 +53,10 @@ impl Default for SpritesWindow {
             selectedcolor: Color32::TRANSPARENT,
             selectedtool: Tool::Pencil,
             viewport: Viewport::default(),
+            importsize: 8,
+            quantize: true,
+            sheetcolumns: 16,
+            selectedanimation: 0,
         }
     }
 }
@@ -54,6 +69,22 @@ impl SpritesWindow {
             if ui.small_button("+").clicked() {
                 app.cart.sprites.push(Sprite::new())
             }
+            for color in app.cart.palette.clone() {
+                let (rect, resp) = ui.allocate_exact_size(vec2(12.0, 12.0), Sense::click());
+                ui.painter().rect(
+                    rect,
+                    0f32,
+                    color,
+                    if color == self.selectedcolor {
+                        Stroke::new(1.0, Color32::WHITE)
+                    } else {
+                        Stroke::none()
+                    },
+                );
+                if resp.clicked() {
+                    self.selectedcolor = color;
+                }
+            }
             match app.cart.sprites.get_mut(app.selectedsprite) {
                 Some(sprite) => {
                     let mut columns = sprite.data.num_columns();
@@ -77,10 +108,44 @@ impl SpritesWindow {
                     while rows > sprite.data.column_len() {
                         sprite.data.addrow(Color32::TRANSPARENT);
                     }
+
+                    ui.separator();
+                    ui.label("flags: ");
+                    for (flag, (name, color)) in FLAGNAMES.iter().zip(FLAGCOLORS).enumerate() {
+                        let on = sprite.flag(flag);
+                        let (rect, resp) = ui.allocate_exact_size(vec2(12.0, 12.0), Sense::click());
+                        if on {
+                            ui.painter().circle_filled(rect.center(), 5.0, color);
+                        } else {
+                            ui.painter()
+                                .circle_stroke(rect.center(), 4.5, Stroke::new(1.0, color));
+                        }
+                        if resp.on_hover_text(format!("{}: {}", flag, name)).clicked() {
+                            sprite.setflag(flag, !on);
+                        }
+                    }
                 }
                 None => (),
             };
         });
+        ui.horizontal(|ui| {
+            ui.add_space(50.0);
+            ui.label("tile size: ");
+            ui.add(egui::DragValue::new(&mut self.importsize).clamp_range(1..=256));
+            ui.checkbox(&mut self.quantize, "quantize to palette");
+            if ui.button("import png").clicked() {
+                spritesheet::import_dialog(app, self.importsize, self.quantize);
+            }
+            ui.separator();
+            ui.label("sheet columns: ");
+            ui.add(egui::DragValue::new(&mut self.sheetcolumns).clamp_range(1..=256));
+            if ui.button("export png").clicked() {
+                spritesheet::export_dialog(app, self.sheetcolumns);
+            }
+        });
+        egui::CollapsingHeader::new("animations").show(ui, |ui| {
+            self.animations_ui(app, ui);
+        });
         match app.cart.sprites.get_mut(app.selectedsprite) {
             Some(sprite) => {
                 let pixelsize = 32.0 / self.viewport.zoom;
@@ -246,16 +311,102 @@ impl SpritesWindow {
         }
     }
 }
+impl SpritesWindow {
+    /// picks an animation and edits its frames in a strip, with a preview playing at game speed
+    fn animations_ui(&mut self, app: &mut FanzApp<'_>, ui: &mut egui::Ui) {
+        let animations = &mut app.cart.animations;
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("animation")
+                .selected_text(
+                    animations
+                        .get(self.selectedanimation)
+                        .map_or("none", |a| a.name.as_str()),
+                )
+                .show_ui(ui, |ui| {
+                    for (i, a) in animations.iter().enumerate() {
+                        ui.selectable_value(&mut self.selectedanimation, i, &a.name);
+                    }
+                });
+            if ui.small_button("+").clicked() {
+                animations.push(Animation::new(format!("anim{}", animations.len())));
+                self.selectedanimation = animations.len() - 1;
+            }
+            if self.selectedanimation < animations.len() && ui.small_button("x").clicked() {
+                animations.remove(self.selectedanimation);
+                self.selectedanimation = self.selectedanimation.saturating_sub(1);
+            }
+        });
+        let animation = match animations.get_mut(self.selectedanimation) {
+            Some(a) => a,
+            None => return,
+        };
+        ui.horizontal(|ui| {
+            ui.label("name: ");
+            ui.add(egui::TextEdit::singleline(&mut animation.name).desired_width(100.0));
+            for mode in [LoopMode::Loop, LoopMode::Once, LoopMode::PingPong] {
+                ui.selectable_value(
+                    &mut animation.mode,
+                    mode,
+                    format!("{:?}", mode).to_lowercase(),
+                );
+            }
+            ui.label(format!("{} frames long", animation.length()));
+        });
+        let sprites = &app.cart.sprites;
+        ui.horizontal(|ui| {
+            // played back the way `anim` does it, 60 game frames a second
+            let time = (ui.input().time * 60.0) as u64;
+            let (rect, _) = ui.allocate_exact_size(vec2(48.0, 48.0), Sense::hover());
+            ui.painter().rect_filled(rect, 0.0, Color32::BLACK);
+            if let Some(sprite) = animation.sprite_at(time).and_then(|s| sprites.get(s)) {
+                draw_sprite(ui.painter(), rect, sprite);
+            }
+            ui.ctx().request_repaint();
+            ui.separator();
 
-#[derive(Serialize, Deserialize)]
-pub struct Sprite {
-    pub data: Array2D<Color32>,
-}
-impl Sprite {
-    pub fn new() -> Sprite {
-        Sprite {
-            data: Array2D::filled_with(Color32::TRANSPARENT, 8, 8),
-        }
+            egui::ScrollArea::horizontal().show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    let mut remove = None;
+                    for (i, frame) in animation.frames.iter_mut().enumerate() {
+                        ui.vertical(|ui| {
+                            let (rect, resp) =
+                                ui.allocate_exact_size(vec2(32.0, 32.0), Sense::click());
+                            ui.painter().rect_filled(rect, 0.0, Color32::BLACK);
+                            if let Some(sprite) = sprites.get(frame.sprite) {
+                                draw_sprite(ui.painter(), rect, sprite);
+                            }
+                            if resp
+                                .on_hover_text("click to use the selected sprite")
+                                .clicked()
+                            {
+                                frame.sprite = app.selectedsprite;
+                            }
+                            ui.add(
+                                egui::DragValue::new(&mut frame.duration)
+                                    .clamp_range(1..=600)
+                                    .suffix("f"),
+                            );
+                            if ui.small_button("x").clicked() {
+                                remove = Some(i);
+                            }
+                        });
+                    }
+                    if let Some(i) = remove {
+                        animation.frames.remove(i);
+                    }
+                    if ui
+                        .button("+")
+                        .on_hover_text("add the selected sprite")
+                        .clicked()
+                    {
+                        animation.frames.push(AnimationFrame {
+                            sprite: app.selectedsprite,
+                            duration: 8,
+                        });
+                    }
+                });
+            });
+        });
     }
 }
 
@@ -0,0 +1,870 @@
+use crate::cart::{Cart, EditorObject, Sprite};
+use crate::spritesheet;
+use crate::FanzApp;
+use array2d::Array2D;
+use egui::{pos2, Color32, RichText};
+use image::RgbaImage;
+use std::path::Path;
+
+// the top 3 bits of a gid are flip flags, we don't support flipping so they get masked off
+const GID_MASK: u32 = 0x1fff_ffff;
+
+/// the parts of a tiled orthogonal map we care about, shared between the tmx and tmj formats
+pub struct TiledMap {
+    pub width: usize,
+    pub height: usize,
+    pub tilewidth: u32,
+    pub tileheight: u32,
+    pub layers: Vec<TiledLayer>,
+    pub tilesets: Vec<TiledTileset>,
+    pub objects: Vec<TiledObject>,
+}
+pub struct TiledLayer {
+    pub name: String,
+    /// row major, `width * height` gids. 0 is an empty tile
+    pub data: Vec<u32>,
+}
+pub struct TiledTileset {
+    pub firstgid: u32,
+    pub name: String,
+    pub image: Option<String>,
+    pub source: Option<String>,
+    pub tilewidth: u32,
+    pub tileheight: u32,
+    /// pixels around the tiles and between them in the image
+    pub margin: u32,
+    pub spacing: u32,
+    pub columns: u32,
+    pub tilecount: u32,
+}
+pub struct TiledObject {
+    pub name: String,
+    pub x: f32,
+    pub y: f32,
+}
+
+pub fn parse_tmj(input: &str) -> Result<TiledMap, String> {
+    let json: serde_json::Value =
+        serde_json::from_str(input).map_err(|e| format!("invalid tmj: {}", e))?;
+    if json["orientation"].as_str().unwrap_or("orthogonal") != "orthogonal" {
+        return Err("only orthogonal maps are supported".into());
+    }
+    let num = |v: &serde_json::Value| v.as_u64().unwrap_or(0) as u32;
+
+    let mut map = TiledMap {
+        width: num(&json["width"]) as usize,
+        height: num(&json["height"]) as usize,
+        tilewidth: num(&json["tilewidth"]),
+        tileheight: num(&json["tileheight"]),
+        layers: vec![],
+        tilesets: vec![],
+        objects: vec![],
+    };
+    for tileset in json["tilesets"].as_array().into_iter().flatten() {
+        map.tilesets.push(TiledTileset {
+            firstgid: num(&tileset["firstgid"]),
+            name: tileset["name"].as_str().unwrap_or("").into(),
+            image: tileset["image"].as_str().map(String::from),
+            source: tileset["source"].as_str().map(String::from),
+            tilewidth: num(&tileset["tilewidth"]),
+            tileheight: num(&tileset["tileheight"]),
+            margin: num(&tileset["margin"]),
+            spacing: num(&tileset["spacing"]),
+            columns: num(&tileset["columns"]),
+            tilecount: num(&tileset["tilecount"]),
+        });
+    }
+    for layer in json["layers"].as_array().into_iter().flatten() {
+        let name = layer["name"].as_str().unwrap_or("").to_string();
+        match layer["type"].as_str() {
+            Some("tilelayer") => {
+                let data = match layer["data"].as_array() {
+                    Some(d) => d.iter().map(num).collect(),
+                    None => {
+                        return Err(format!(
+                            "layer \"{}\" is not csv encoded. set the tile layer format to CSV in tiled",
+                            name
+                        ))
+                    }
+                };
+                map.layers.push(TiledLayer { name, data });
+            }
+            Some("objectgroup") => {
+                for obj in layer["objects"].as_array().into_iter().flatten() {
+                    map.objects.push(TiledObject {
+                        name: obj["name"].as_str().unwrap_or("").into(),
+                        x: obj["x"].as_f64().unwrap_or(0.0) as f32,
+                        y: obj["y"].as_f64().unwrap_or(0.0) as f32,
+                    });
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(map)
+}
+
+pub fn parse_tmx(input: &str) -> Result<TiledMap, String> {
+    let doc = roxmltree::Document::parse(input).map_err(|e| format!("invalid tmx: {}", e))?;
+    let root = doc.root_element();
+    if !root.has_tag_name("map") {
+        return Err("not a tiled map".into());
+    }
+    if root.attribute("orientation").unwrap_or("orthogonal") != "orthogonal" {
+        return Err("only orthogonal maps are supported".into());
+    }
+    let num = |node: &roxmltree::Node<'_, '_>, attr: &str| -> u32 {
+        node.attribute(attr)
+            .and_then(|a| a.parse().ok())
+            .unwrap_or(0)
+    };
+    let float = |node: &roxmltree::Node<'_, '_>, attr: &str| -> f32 {
+        node.attribute(attr)
+            .and_then(|a| a.parse().ok())
+            .unwrap_or(0.0)
+    };
+
+    let mut map = TiledMap {
+        width: num(&root, "width") as usize,
+        height: num(&root, "height") as usize,
+        tilewidth: num(&root, "tilewidth"),
+        tileheight: num(&root, "tileheight"),
+        layers: vec![],
+        tilesets: vec![],
+        objects: vec![],
+    };
+    for node in root.children().filter(|n| n.is_element()) {
+        match node.tag_name().name() {
+            "tileset" => {
+                let image = node
+                    .children()
+                    .find(|n| n.has_tag_name("image"))
+                    .and_then(|n| n.attribute("source"))
+                    .map(String::from);
+                map.tilesets.push(TiledTileset {
+                    firstgid: num(&node, "firstgid"),
+                    name: node.attribute("name").unwrap_or("").into(),
+                    image,
+                    source: node.attribute("source").map(String::from),
+                    tilewidth: num(&node, "tilewidth"),
+                    tileheight: num(&node, "tileheight"),
+                    margin: num(&node, "margin"),
+                    spacing: num(&node, "spacing"),
+                    columns: num(&node, "columns"),
+                    tilecount: num(&node, "tilecount"),
+                });
+            }
+            "layer" => {
+                let name = node.attribute("name").unwrap_or("").to_string();
+                let data = match node.children().find(|n| n.has_tag_name("data")) {
+                    Some(d) => d,
+                    None => continue,
+                };
+                if data.attribute("encoding") != Some("csv") {
+                    return Err(format!(
+                        "layer \"{}\" is not csv encoded. set the tile layer format to CSV in tiled",
+                        name
+                    ));
+                }
+                let data = data
+                    .text()
+                    .unwrap_or("")
+                    .split(',')
+                    .map(|s| s.trim())
+                    .filter(|s| !s.is_empty())
+                    .map(|s| {
+                        s.parse::<u32>()
+                            .map_err(|e| format!("bad tile \"{}\": {}", s, e))
+                    })
+                    .collect::<Result<Vec<u32>, String>>()?;
+                map.layers.push(TiledLayer { name, data });
+            }
+            "objectgroup" => {
+                for obj in node.children().filter(|n| n.has_tag_name("object")) {
+                    map.objects.push(TiledObject {
+                        name: obj.attribute("name").unwrap_or("").into(),
+                        x: float(&obj, "x"),
+                        y: float(&obj, "y"),
+                    });
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(map)
+}
+
+/// copies a parsed map into a scene of the cart. tile layers are flattened into the scene's map
+/// (later layers win), objects move the scene's editor object with the same name or get added as new ones.
+/// returns a list of things that were skipped or approximated
+pub fn apply(
+    cart: &mut Cart,
+    scene: usize,
+    map: &TiledMap,
+    dir: &Path,
+    loadimages: bool,
+) -> Vec<String> {
+    let mut report = vec![];
+
+    // gid -> sprite index, one entry per tileset
+    let mut tilesets = map.tilesets.iter().collect::<Vec<_>>();
+    tilesets.sort_by_key(|t| t.firstgid);
+    let mut spritebases = vec![];
+    for tileset in &tilesets {
+        // tilesets that aren't loaded are taken to be the cart's own sprites, gid 1 is sprite 0.
+        // that's what `from_cart` writes
+        let indices = tileset.firstgid.saturating_sub(1) as usize;
+        let base = match (&tileset.image, loadimages) {
+            (Some(image), true) => match load(tileset, map, &dir.join(image)) {
+                Ok(sprites) => {
+                    let base = cart.sprites.len();
+                    cart.sprites.extend(sprites);
+                    base
+                }
+                Err(e) => {
+                    report.push(format!("{}, using sprite indices instead", e));
+                    indices
+                }
+            },
+            _ => {
+                if let Some(source) = &tileset.source {
+                    report.push(format!(
+                        "external tileset {} not loaded, using sprite indices instead",
+                        source
+                    ));
+                }
+                indices
+            }
+        };
+        spritebases.push((tileset.firstgid, base));
+    }
+    let togid = |gid: u32| -> Option<usize> {
+        let gid = gid & GID_MASK;
+        if gid == 0 {
+            return None;
+        }
+        let (firstgid, base) = spritebases
+            .iter()
+            .rev()
+            .find(|(firstgid, _)| *firstgid <= gid)
+            .copied()
+            .unwrap_or((1, 0));
+        Some(base + (gid - firstgid) as usize)
+    };
+
+    if map.width > 0 && map.height > 0 {
+        let mut newmap = Array2D::filled_with(None, map.width, map.height);
+        // tiles pointing past the sprites the cart has, from tilesets whose image wasn't loaded
+        let mut missing = 0;
+        for layer in &map.layers {
+            if layer.data.len() != map.width * map.height {
+                report.push(format!(
+                    "layer \"{}\" has the wrong size, skipped",
+                    layer.name
+                ));
+                continue;
+            }
+            for (i, gid) in layer.data.iter().enumerate() {
+                match togid(*gid) {
+                    Some(sprite) if sprite >= cart.sprites.len() => missing += 1,
+                    Some(sprite) => {
+                        *newmap.get_mut(i % map.width, i / map.width).unwrap() = Some(sprite)
+                    }
+                    None => (),
+                }
+            }
+        }
+        if missing > 0 {
+            report.push(format!(
+                "{} tiles use sprites the cart doesn't have, left empty",
+                missing
+            ));
+        }
+        if map.layers.len() > 1 {
+            report.push(format!(
+                "{} tile layers were flattened into one",
+                map.layers.len()
+            ));
+        }
+        cart.scenes[scene].map = newmap;
+    }
+
+    for (i, obj) in map.objects.iter().enumerate() {
+        let name = if obj.name.is_empty() {
+            format!("Object {}", cart.objects.len() + i)
+        } else {
+            obj.name.clone()
+        };
+        match cart
+            .objects
+            .iter()
+            .position(|o| o.scene == scene && o.name == name)
+        {
+            // tiled positions are in the scene, ours are relative to the parent
+            Some(existing) => {
+                let parentpos = match cart.parent(existing) {
+                    Some(p) => cart.worldpos(p).to_vec2(),
+                    None => egui::Vec2::ZERO,
+                };
+                cart.objects[existing].pos = pos2(obj.x, obj.y) - parentpos;
+            }
+            None => {
+                let mut new =
+                    EditorObject::new(name, pos2(obj.x, obj.y), "put \"Hello World\"".into());
+                new.scene = scene;
+                cart.objects.push(new);
+            }
+        }
+    }
+    report
+}
+
+/// cuts a tileset's image into sprites. our sprites are square, so its tiles have to be too
+fn load(tileset: &TiledTileset, map: &TiledMap, path: &Path) -> Result<Vec<Sprite>, String> {
+    let or = |n: u32, fallback: u32| if n > 0 { n } else { fallback };
+    let width = or(tileset.tilewidth, map.tilewidth);
+    let height = or(tileset.tileheight, map.tileheight);
+    if width != height {
+        return Err(format!(
+            "tileset {} has {}x{} tiles, only square ones can be loaded",
+            tileset.name, width, height
+        ));
+    }
+    let img = unpad(
+        &spritesheet::load_png(path)?,
+        width,
+        tileset.margin,
+        tileset.spacing,
+    );
+    let mut sprites = spritesheet::image_to_sprites(&img, width as usize, None);
+    if tileset.tilecount > 0 {
+        sprites.truncate(tileset.tilecount as usize);
+    }
+    Ok(sprites)
+}
+/// the tiles of a tileset image packed edge to edge, without the margin around them
+/// and the spacing between them
+fn unpad(img: &RgbaImage, size: u32, margin: u32, spacing: u32) -> RgbaImage {
+    if margin == 0 && spacing == 0 {
+        return img.clone();
+    }
+    let size = size.max(1);
+    let step = size + spacing;
+    let fit = |length: u32| (length.saturating_sub(2 * margin) + spacing) / step;
+    let (columns, rows) = (fit(img.width()), fit(img.height()));
+    let mut out = RgbaImage::new(columns * size, rows * size);
+    for row in 0..rows {
+        for column in 0..columns {
+            for x in 0..size {
+                for y in 0..size {
+                    let pixel = img.get_pixel(margin + column * step + x, margin + row * step + y);
+                    out.put_pixel(column * size + x, row * size + y, *pixel);
+                }
+            }
+        }
+    }
+    out
+}
+
+/// builds a tiled map out of a scene of the cart. the tileset points at `sheetname`, which
+/// should be the sprite sheet exported with `columns` sprites per row
+pub fn from_cart(cart: &Cart, scene: usize, sheetname: &str, columns: usize) -> TiledMap {
+    // same cell size `sprites_to_image` uses
+    let tilewidth = cart
+        .sprites
+        .iter()
+        .map(|s| s.data.num_rows())
+        .max()
+        .unwrap_or(8) as u32;
+    let tileheight = cart
+        .sprites
+        .iter()
+        .map(|s| s.data.num_columns())
+        .max()
+        .unwrap_or(8) as u32;
+    let tiles = &cart.scenes[scene].map;
+    let width = tiles.num_rows();
+    let height = tiles.num_columns();
+    let mut data = vec![0; width * height];
+    for x in 0..width {
+        for y in 0..height {
+            if let Some(s) = tiles.get(x, y).unwrap() {
+                data[y * width + x] = *s as u32 + 1;
+            }
+        }
+    }
+    TiledMap {
+        width,
+        height,
+        tilewidth,
+        tileheight,
+        layers: vec![TiledLayer {
+            name: "map".into(),
+            data,
+        }],
+        tilesets: vec![TiledTileset {
+            firstgid: 1,
+            name: "sprites".into(),
+            image: Some(sheetname.into()),
+            source: None,
+            tilewidth,
+            tileheight,
+            margin: 0,
+            spacing: 0,
+            columns: columns as u32,
+            tilecount: cart.sprites.len() as u32,
+        }],
+        objects: (0..cart.objects.len())
+            .filter(|i| cart.objects[*i].scene == scene)
+            .map(|i| {
+                let pos = cart.worldpos(i);
+                TiledObject {
+                    name: cart.objects[i].name.clone(),
+                    x: pos.x,
+                    y: pos.y,
+                }
+            })
+            .collect(),
+    }
+}
+
+fn sheetsize(tileset: &TiledTileset) -> (u32, u32) {
+    let columns = tileset.columns.max(1);
+    let rows = ((tileset.tilecount + columns - 1) / columns).max(1);
+    let size = |count: u32, tile: u32| {
+        2 * tileset.margin + count * (tile + tileset.spacing) - tileset.spacing
+    };
+    (
+        size(columns, tileset.tilewidth),
+        size(rows, tileset.tileheight),
+    )
+}
+
+pub fn write_tmj(map: &TiledMap) -> String {
+    let tilesets = map
+        .tilesets
+        .iter()
+        .map(|t| {
+            let (imagewidth, imageheight) = sheetsize(t);
+            serde_json::json!({
+                "firstgid": t.firstgid,
+                "name": t.name,
+                "image": t.image,
+                "imagewidth": imagewidth,
+                "imageheight": imageheight,
+                "tilewidth": t.tilewidth,
+                "tileheight": t.tileheight,
+                "columns": t.columns,
+                "tilecount": t.tilecount,
+                "margin": t.margin,
+                "spacing": t.spacing,
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut layers = map
+        .layers
+        .iter()
+        .enumerate()
+        .map(|(i, l)| {
+            serde_json::json!({
+                "id": i + 1,
+                "type": "tilelayer",
+                "name": l.name,
+                "width": map.width,
+                "height": map.height,
+                "x": 0,
+                "y": 0,
+                "opacity": 1,
+                "visible": true,
+                "data": l.data,
+            })
+        })
+        .collect::<Vec<_>>();
+    layers.push(serde_json::json!({
+        "id": map.layers.len() + 1,
+        "type": "objectgroup",
+        "name": "objects",
+        "draworder": "topdown",
+        "x": 0,
+        "y": 0,
+        "opacity": 1,
+        "visible": true,
+        "objects": map.objects.iter().enumerate().map(|(i, o)| serde_json::json!({
+            "id": i + 1,
+            "name": o.name,
+            "x": o.x,
+            "y": o.y,
+            "width": 0,
+            "height": 0,
+            "rotation": 0,
+            "point": true,
+            "visible": true,
+        })).collect::<Vec<_>>(),
+    }));
+    let json = serde_json::json!({
+        "type": "map",
+        "version": "1.8",
+        "orientation": "orthogonal",
+        "renderorder": "right-down",
+        "infinite": false,
+        "width": map.width,
+        "height": map.height,
+        "tilewidth": map.tilewidth,
+        "tileheight": map.tileheight,
+        "nextlayerid": map.layers.len() + 2,
+        "nextobjectid": map.objects.len() + 1,
+        "tilesets": tilesets,
+        "layers": layers,
+    });
+    serde_json::to_string_pretty(&json).unwrap()
+}
+
+pub fn write_tmx(map: &TiledMap) -> String {
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<map version=\"1.8\" orientation=\"orthogonal\" renderorder=\"right-down\" width=\"{}\" height=\"{}\" tilewidth=\"{}\" tileheight=\"{}\" infinite=\"0\" nextlayerid=\"{}\" nextobjectid=\"{}\">\n",
+        map.width,
+        map.height,
+        map.tilewidth,
+        map.tileheight,
+        map.layers.len() + 2,
+        map.objects.len() + 1
+    ));
+    for t in &map.tilesets {
+        let (imagewidth, imageheight) = sheetsize(t);
+        out.push_str(&format!(
+            " <tileset firstgid=\"{}\" name=\"{}\" tilewidth=\"{}\" tileheight=\"{}\" spacing=\"{}\" margin=\"{}\" tilecount=\"{}\" columns=\"{}\">\n",
+            t.firstgid,
+            escape(&t.name),
+            t.tilewidth,
+            t.tileheight,
+            t.spacing,
+            t.margin,
+            t.tilecount,
+            t.columns
+        ));
+        if let Some(image) = &t.image {
+            out.push_str(&format!(
+                "  <image source=\"{}\" width=\"{}\" height=\"{}\"/>\n",
+                escape(image),
+                imagewidth,
+                imageheight
+            ));
+        }
+        out.push_str(" </tileset>\n");
+    }
+    for (i, l) in map.layers.iter().enumerate() {
+        out.push_str(&format!(
+            " <layer id=\"{}\" name=\"{}\" width=\"{}\" height=\"{}\">\n  <data encoding=\"csv\">\n",
+            i + 1,
+            escape(&l.name),
+            map.width,
+            map.height
+        ));
+        let rows = l
+            .data
+            .chunks(map.width.max(1))
+            .map(|row| {
+                row.iter()
+                    .map(|g| g.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect::<Vec<_>>();
+        out.push_str(&rows.join(",\n"));
+        out.push_str("\n  </data>\n </layer>\n");
+    }
+    out.push_str(&format!(
+        " <objectgroup id=\"{}\" name=\"objects\">\n",
+        map.layers.len() + 1
+    ));
+    for (i, o) in map.objects.iter().enumerate() {
+        out.push_str(&format!(
+            "  <object id=\"{}\" name=\"{}\" x=\"{}\" y=\"{}\">\n   <point/>\n  </object>\n",
+            i + 1,
+            escape(&o.name),
+            o.x,
+            o.y
+        ));
+    }
+    out.push_str(" </objectgroup>\n</map>\n");
+    out
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn import_dialog(app: &mut FanzApp<'_>, loadimages: bool) {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let path = match rfd::FileDialog::new()
+            .add_filter("tiled map", &["tmx", "tmj", "json"])
+            .pick_file()
+        {
+            Some(p) => p,
+            None => return,
+        };
+        let parsed = std::fs::read_to_string(&path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))
+            .and_then(|input| match path.extension().and_then(|e| e.to_str()) {
+                Some("tmx") => parse_tmx(&input),
+                _ => parse_tmj(&input),
+            });
+        match parsed {
+            Ok(map) => {
+                let dir = path.parent().unwrap_or_else(|| Path::new("."));
+                for skipped in apply(&mut app.cart, app.scene, &map, dir, loadimages) {
+                    app.output
+                        .push(RichText::new(skipped).color(Color32::YELLOW));
+                }
+                app.output.push(
+                    RichText::new(format!("imported {}", path.display())).color(Color32::GREEN),
+                );
+            }
+            Err(e) => app.output.push(RichText::new(e).color(Color32::RED)),
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = loadimages;
+        app.output
+            .push(RichText::new("tiled import is not supported on web").color(Color32::RED));
+    }
+}
+
+/// writes the map as tmx or tmj depending on the chosen extension, plus the sprite sheet next to it
+pub fn export_dialog(app: &mut FanzApp<'_>, columns: usize) {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let path = match rfd::FileDialog::new()
+            .add_filter("tiled map", &["tmx", "tmj"])
+            .set_file_name("map.tmx")
+            .save_file()
+        {
+            Some(p) => p,
+            None => return,
+        };
+        let stem = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("map")
+            .to_string();
+        let sheetname = format!("{}_sprites.png", stem);
+        let map = from_cart(&app.cart, app.scene, &sheetname, columns.max(1));
+        let contents = match path.extension().and_then(|e| e.to_str()) {
+            Some("tmj") | Some("json") => write_tmj(&map),
+            _ => write_tmx(&map),
+        };
+        let sheet = spritesheet::sprites_to_image(&app.cart.sprites, columns);
+        let result =
+            spritesheet::save_png(&path.with_file_name(&sheetname), &sheet).and_then(|_| {
+                std::fs::write(&path, contents)
+                    .map_err(|e| format!("could not write {}: {}", path.display(), e))
+            });
+        match result {
+            Ok(()) => app.output.push(
+                RichText::new(format!("exported tiled map to {}", path.display()))
+                    .color(Color32::GREEN),
+            ),
+            Err(e) => app.output.push(RichText::new(e).color(Color32::RED)),
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = columns;
+        app.output
+            .push(RichText::new("tiled export is not supported on web").color(Color32::RED));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cart::Sprite;
+
+    fn sample() -> TiledMap {
+        TiledMap {
+            width: 3,
+            height: 2,
+            tilewidth: 8,
+            tileheight: 8,
+            layers: vec![TiledLayer {
+                name: "ground & \"sky\"".into(),
+                data: vec![0, 1, 2, 3, 0, 0x8000_0002],
+            }],
+            tilesets: vec![TiledTileset {
+                firstgid: 1,
+                name: "sprites".into(),
+                image: Some("sheet <1>.png".into()),
+                source: None,
+                tilewidth: 8,
+                tileheight: 8,
+                margin: 1,
+                spacing: 2,
+                columns: 16,
+                tilecount: 3,
+            }],
+            objects: vec![TiledObject {
+                name: "player".into(),
+                x: 12.5,
+                y: 4.0,
+            }],
+        }
+    }
+    fn assert_same(a: &TiledMap, b: &TiledMap) {
+        assert_eq!((a.width, a.height), (b.width, b.height));
+        assert_eq!((a.tilewidth, a.tileheight), (b.tilewidth, b.tileheight));
+        assert_eq!(a.layers.len(), b.layers.len());
+        for (la, lb) in a.layers.iter().zip(&b.layers) {
+            assert_eq!(la.name, lb.name);
+            assert_eq!(la.data, lb.data);
+        }
+        assert_eq!(a.tilesets.len(), b.tilesets.len());
+        for (ta, tb) in a.tilesets.iter().zip(&b.tilesets) {
+            assert_eq!(ta.firstgid, tb.firstgid);
+            assert_eq!(ta.name, tb.name);
+            assert_eq!(ta.image, tb.image);
+            assert_eq!(ta.source, tb.source);
+            assert_eq!(
+                (ta.tilewidth, ta.tileheight, ta.columns, ta.tilecount),
+                (tb.tilewidth, tb.tileheight, tb.columns, tb.tilecount)
+            );
+            assert_eq!((ta.margin, ta.spacing), (tb.margin, tb.spacing));
+        }
+        assert_eq!(a.objects.len(), b.objects.len());
+        for (oa, ob) in a.objects.iter().zip(&b.objects) {
+            assert_eq!((&oa.name, oa.x, oa.y), (&ob.name, ob.x, ob.y));
+        }
+    }
+
+    #[test]
+    fn tmx_round_trip() {
+        let map = sample();
+        assert_same(&map, &parse_tmx(&write_tmx(&map)).unwrap());
+    }
+
+    #[test]
+    fn tmj_round_trip() {
+        let map = sample();
+        assert_same(&map, &parse_tmj(&write_tmj(&map)).unwrap());
+    }
+
+    #[test]
+    fn parse_rejects_what_we_cant_read() {
+        let iso = "<map orientation=\"isometric\" width=\"1\" height=\"1\"></map>";
+        assert!(parse_tmx(iso).is_err());
+        let base64 = "<map width=\"1\" height=\"1\"><layer name=\"a\"><data encoding=\"base64\">AAAA</data></layer></map>";
+        assert!(matches!(parse_tmx(base64), Err(e) if e.contains("csv")));
+        assert!(parse_tmx("<notamap/>").is_err());
+        assert!(parse_tmj("{\"orientation\": \"hexagonal\"}").is_err());
+        let base64 = r#"{"layers": [{"type": "tilelayer", "name": "a", "data": "AAAA"}]}"#;
+        assert!(matches!(parse_tmj(base64), Err(e) if e.contains("csv")));
+    }
+
+    #[test]
+    fn apply_flattens_layers_and_skips_missing_sprites() {
+        let mut cart = Cart {
+            sprites: vec![Sprite::new(), Sprite::new(), Sprite::new(), Sprite::new()],
+            ..Default::default()
+        };
+        let mut map = sample();
+        map.width = 2;
+        map.height = 2;
+        map.tilesets[0].image = None;
+        map.layers = vec![
+            TiledLayer {
+                name: "a".into(),
+                data: vec![1, 0, 2, 0],
+            },
+            TiledLayer {
+                name: "b".into(),
+                // flipped, then one past the cart's sprites
+                data: vec![0, 0x8000_0003, 0, 9],
+            },
+        ];
+        let report = apply(&mut cart, 0, &map, Path::new("."), false);
+        let tiles = &cart.scenes[0].map;
+        assert_eq!((tiles.num_rows(), tiles.num_columns()), (2, 2));
+        assert_eq!(tiles.get(0, 0), Some(&Some(0)));
+        assert_eq!(tiles.get(1, 0), Some(&Some(2)));
+        assert_eq!(tiles.get(0, 1), Some(&Some(1)));
+        assert_eq!(tiles.get(1, 1), Some(&None));
+        assert!(report.iter().any(|r| r.starts_with("1 tiles")));
+
+        assert_eq!(cart.objects.len(), 1);
+        assert_eq!(cart.objects[0].name, "player");
+        // a second import moves the same object instead of adding another
+        map.objects[0].x = 20.0;
+        apply(&mut cart, 0, &map, Path::new("."), false);
+        assert_eq!(cart.objects.len(), 1);
+        assert_eq!(cart.objects[0].pos, pos2(20.0, 4.0));
+    }
+
+    #[test]
+    fn apply_keeps_unloaded_tilesets_apart() {
+        let mut cart = Cart {
+            sprites: (0..8).map(|_| Sprite::new()).collect(),
+            ..Default::default()
+        };
+        let mut map = sample();
+        map.width = 2;
+        map.height = 1;
+        let mut second = sample().tilesets.remove(0);
+        second.firstgid = 5;
+        second.image = None;
+        map.tilesets[0].image = None;
+        map.tilesets.push(second);
+        map.layers[0].data = vec![2, 6];
+        apply(&mut cart, 0, &map, Path::new("."), false);
+        let tiles = &cart.scenes[0].map;
+        assert_eq!(tiles.get(0, 0), Some(&Some(1)));
+        assert_eq!(tiles.get(1, 0), Some(&Some(5)));
+    }
+
+    #[test]
+    fn apply_refuses_tiles_that_arent_square() {
+        let mut cart = Cart::default();
+        let mut map = sample();
+        map.tilesets[0].tileheight = 16;
+        let report = apply(&mut cart, 0, &map, Path::new("."), true);
+        assert!(report.iter().any(|r| r.contains("8x16")), "{:?}", report);
+        assert!(cart.sprites.len() <= 1);
+    }
+
+    #[test]
+    fn unpad_drops_margin_and_spacing() {
+        // 2x2 tiles, a margin of 1 and a spacing of 1, tile n filled with gray n * 10
+        let mut img = RgbaImage::from_pixel(7, 4, image::Rgba([255, 0, 0, 255]));
+        for (n, (tx, ty)) in [(1, 1), (4, 1)].iter().enumerate() {
+            for x in 0..2 {
+                for y in 0..2 {
+                    let gray = n as u8 * 10;
+                    img.put_pixel(tx + x, ty + y, image::Rgba([gray, gray, gray, 255]));
+                }
+            }
+        }
+        let out = unpad(&img, 2, 1, 1);
+        assert_eq!(out.dimensions(), (4, 2));
+        assert!(out.pixels().all(|p| p.0[0] != 255));
+        assert_eq!(out.get_pixel(3, 1).0, [10, 10, 10, 255]);
+        assert_eq!(unpad(&img, 2, 0, 0).dimensions(), (7, 4));
+    }
+
+    #[test]
+    fn from_cart_uses_gids_from_one() {
+        let mut cart = Cart {
+            sprites: vec![Sprite::new(), Sprite::new()],
+            ..Default::default()
+        };
+        *cart.scenes[0].map.get_mut(1, 0).unwrap() = Some(1);
+        let map = from_cart(&cart, 0, "sheet.png", 16);
+        assert_eq!((map.width, map.height), (8, 8));
+        assert_eq!(&map.layers[0].data[..3], &[0, 2, 0]);
+        assert_eq!(map.tilesets[0].tilecount, 2);
+    }
+}
@@ -0,0 +1,78 @@
+use crate::gamewindow::GameWindow;
+use crate::runner::WatchValue;
+
+/// every running object, spawned copies too, and its top level variables. numbers, strings and bools can be edited
+#[derive(Default)]
+pub struct WatchWindow {
+    pub enabled: bool,
+    /// object id, variable name and text of the string being typed into.
+    /// it's only sent once done, the game would overwrite it with every frame otherwise
+    editing: Option<((u64, String), String)>,
+}
+impl WatchWindow {
+    pub fn ui(&mut self, game: &mut GameWindow, ui: &mut egui::Ui) {
+        if game.game.is_none() {
+            ui.label("not running");
+            return;
+        }
+        let mut edits = vec![];
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for (i, obj) in game.watch.iter().enumerate() {
+                egui::CollapsingHeader::new(format!("{} #{}", obj.name, obj.id))
+                    .id_source(("watch", i))
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        egui::Grid::new(("watch_grid", i))
+                            .striped(true)
+                            .show(ui, |ui| {
+                                for (name, value) in &obj.variables {
+                                    ui.monospace(name);
+                                    let mut edited = value.clone();
+                                    match &mut edited {
+                                        WatchValue::Number(n) => {
+                                            ui.add(egui::DragValue::new(n).speed(0.1));
+                                        }
+                                        WatchValue::String(s) => {
+                                            let key = (obj.id, name.clone());
+                                            let mut text = match &self.editing {
+                                                Some((k, text)) if *k == key => text.clone(),
+                                                _ => s.clone(),
+                                            };
+                                            let resp = ui.text_edit_singleline(&mut text);
+                                            if resp.lost_focus() {
+                                                // enter or clicking away keeps it, escape doesn't
+                                                let cancelled =
+                                                    ui.input().key_pressed(egui::Key::Escape);
+                                                if !cancelled && text != *s {
+                                                    edits.push((
+                                                        obj.id,
+                                                        name.clone(),
+                                                        WatchValue::String(text),
+                                                    ));
+                                                }
+                                                self.editing = None;
+                                            } else if resp.has_focus() {
+                                                self.editing = Some((key, text));
+                                            }
+                                        }
+                                        WatchValue::Bool(b) => {
+                                            ui.checkbox(b, "");
+                                        }
+                                        WatchValue::Other(s) => {
+                                            ui.monospace(s.as_str());
+                                        }
+                                    }
+                                    if edited != *value {
+                                        edits.push((obj.id, name.clone(), edited));
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                    });
+            }
+        });
+        for (object, name, value) in edits {
+            game.setvariable(object, name, value);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::FanzApp;
+use egui::{Color32, RichText};
+
+/// the page the exported player boots from. `start_player` gets the cart json fetched next to it
+const INDEX_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<meta http-equiv="Content-Type" content="text/html; charset=utf-8" />
+<head>
+    <meta name="viewport" content="width=device-width, initial-scale=1.0, user-scalable=no">
+    <title>{title}</title>
+    <style>
+        html, body {
+            overflow: hidden;
+            margin: 0 !important;
+            padding: 0 !important;
+            height: 100%;
+            width: 100%;
+            background: #000;
+        }
+        canvas {
+            margin-right: auto;
+            margin-left: auto;
+            display: block;
+            position: absolute;
+            top: 0%;
+            left: 50%;
+            transform: translate(-50%, 0%);
+        }
+    </style>
+</head>
+<body>
+    <canvas id="the_canvas_id"></canvas>
+    <script src="fan_z.js"></script>
+    <script>
+        // we'll defer our execution until the wasm is ready to go
+        delete WebAssembly.instantiateStreaming;
+        wasm_bindgen("./fan_z_bg.wasm")
+            .then(() => fetch("./cart.json"))
+            .then((response) => response.text())
+            .then((cart) => wasm_bindgen.start_player("the_canvas_id", cart))
+            .catch(console.error);
+    </script>
+</body>
+</html>
+"#;
+
+/// files produced by `build_web.sh` that every exported game needs
+const WASM_FILES: [&str; 2] = ["fan_z.js", "fan_z_bg.wasm"];
+
+pub fn index_html(title: &str) -> String {
+    INDEX_HTML.replace("{title}", &title.replace('&', "&amp;").replace('<', "&lt;"))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn hasbuild(dir: &std::path::Path) -> bool {
+    WASM_FILES.iter().all(|f| dir.join(f).is_file())
+}
+/// looks for the web player in docs/ next to the executable or any folder above it, which covers
+/// running from target/, then in the working directory
+#[cfg(not(target_arch = "wasm32"))]
+pub fn find_build() -> Option<std::path::PathBuf> {
+    let mut candidates = vec![];
+    if let Ok(exe) = std::env::current_exe() {
+        for dir in exe.ancestors().skip(1) {
+            candidates.push(dir.join("docs"));
+            candidates.push(dir.to_path_buf());
+        }
+    }
+    if let Ok(cwd) = std::env::current_dir() {
+        candidates.push(cwd.join("docs"));
+    }
+    candidates.into_iter().find(|d| hasbuild(d))
+}
+
+/// writes index.html, cart.json and the wasm player from `builddir` into a folder,
+/// ready to be zipped and uploaded
+#[cfg(not(target_arch = "wasm32"))]
+pub fn export(
+    app: &FanzApp<'_>,
+    builddir: &std::path::Path,
+    dir: &std::path::Path,
+) -> Result<(), String> {
+    for file in WASM_FILES {
+        let from = builddir.join(file);
+        std::fs::copy(&from, dir.join(file)).map_err(|e| {
+            format!(
+                "could not copy {}: {}. build the web version with ./build_web.sh first",
+                from.display(),
+                e
+            )
+        })?;
+    }
+    let cart =
+        serde_json::to_string(&app.cart).map_err(|e| format!("could not save cart: {}", e))?;
+    std::fs::write(dir.join("cart.json"), cart)
+        .map_err(|e| format!("could not write cart.json: {}", e))?;
+
+    let title = dir.file_name().and_then(|n| n.to_str()).unwrap_or("fan-z");
+    std::fs::write(dir.join("index.html"), index_html(title))
+        .map_err(|e| format!("could not write index.html: {}", e))
+}
+
+pub fn export_dialog(app: &mut FanzApp<'_>) {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        // the folder used last time, or wherever it can be found, or ask
+        let builddir = match app
+            .webbuild
+            .clone()
+            .filter(|d| hasbuild(d))
+            .or_else(find_build)
+        {
+            Some(d) => d,
+            None => match rfd::FileDialog::new()
+                .set_title("Folder with fan_z_bg.wasm (made by build_web.sh)")
+                .pick_folder()
+            {
+                Some(d) => d,
+                None => return,
+            },
+        };
+        let dir = match rfd::FileDialog::new()
+            .set_title("Export web player to")
+            .pick_folder()
+        {
+            Some(d) => d,
+            None => return,
+        };
+        let exported = export(app, &builddir, &dir);
+        if exported.is_ok() {
+            app.webbuild = Some(builddir);
+        }
+        match exported {
+            Ok(()) => app.output.push(
+                RichText::new(format!("exported web player to {}", dir.display()))
+                    .color(Color32::GREEN),
+            ),
+            Err(e) => app.output.push(RichText::new(e).color(Color32::RED)),
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    app.output
+        .push(RichText::new("web export is not supported on web").color(Color32::RED));
+}
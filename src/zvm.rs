@@ -1,17 +1,40 @@
+use crate::cart::{Animation, Budget, EditorObject, Sprite};
+use crate::collision::Body;
 use crate::consolebuiltins::{self, ZColor};
+use crate::debugger::{self, Debugger};
+use crate::runner::WatchValue;
+use array2d::Array2D;
 use egui::{Color32, Pos2, Rect};
+use instant::Instant;
 use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
 use std::sync::Mutex;
 use std::{cell::RefCell, rc::Rc};
 use zsp_core::{
     builtins,
     exceptions::Exception,
     lexer, parser,
-    runtime::{self, FunctionType, RFunction, Scope, ScopeType},
+    runtime::{self, FunctionType, RFunction, Scope, ScopeType, Value},
 };
 
-// raw pointers
-pub static mut STATE_PTR: *mut ZVMState = std::ptr::null_mut();
+// one state per thread, so every game thread gets its own
+thread_local! {
+    static STATE: RefCell<ZVMState> = RefCell::new(ZVMState::new(Budget::default()));
+}
+
+/// runs `f` on the state of the game running on this thread. builtins have no other way to reach it.
+/// panics when it's already in use, so don't run scripts from `f`, their builtins need it too
+pub fn with_state<R>(f: impl FnOnce(&mut ZVMState) -> R) -> R {
+    STATE.with(|s| f(&mut s.borrow_mut()))
+}
+/// drops what the last game on this thread left behind, like its sprites and map
+pub fn freestate() {
+    initstate(Budget::default());
+}
+/// resets this thread's state for a fresh game
+pub fn initstate(limits: Budget) {
+    with_state(|s| *s = ZVMState::new(limits));
+}
 
 #[derive(Debug)]
 pub enum ZEvent {
@@ -34,24 +57,241 @@ pub enum ZEvent {
     },
     Put(String),
 }
-pub struct ZVM<'a> {
+pub struct ZVM {
     pub contents: String,
     pub functions: HashMap<String, RFunction>,
-    pub root_scope: Rc<RefCell<Scope<'a>>>,
+    pub root_scope: Rc<RefCell<Scope<'static>>>,
+    /// the script as written. differs from `contents` when it was instrumented for the debugger
+    pub source: String,
+    pub inserted: Vec<(usize, usize)>,
+    pub debug: bool,
 }
 #[derive(Debug)]
 pub struct ZVMState {
-    pub buffer: Vec<ZEvent>,
+    /// what the scripts drew this frame, and on which layer
+    pub buffer: Vec<(usize, ZEvent)>,
+    /// where `push` puts events, see `cart::LAYERS`
+    pub layer: usize,
     pub keys: Vec<String>,
+    /// mouse position in game pixels, None when it's outside the game screen
+    pub mouse: Option<(f32, f32)>,
+
+    pub limits: Budget,
+    /// work done by the script call that's currently running, see `tick`
+    pub instructions: u64,
+    pub callstart: Instant,
+
+    pub debug: Debugger,
+    /// position of the object whose script is running, in game pixels
+    pub pos: Pos2,
+    /// id of the object whose script is running
+    pub id: u64,
+    /// ids handed out so far, the first object gets 1
+    pub nextid: u64,
+    /// names of the cart's objects, in cart order. what `spawn` can make
+    pub names: Vec<String>,
+    /// names of the cart's scenes, what `goto_scene` can switch to
+    pub scenes: Vec<String>,
+    /// scene `goto_scene` asked for, switched to once every object ran
+    pub goto: Option<usize>,
+    /// tags of the cart's objects, in cart order
+    pub tags: Vec<Vec<String>>,
+    /// copies of the cart's sprites and the running scene's map, for collision checks
+    pub sprites: Vec<Sprite>,
+    pub map: Array2D<Option<usize>>,
+    /// every running object in run order, as of when it last ran
+    pub bodies: Vec<Body>,
+    pub animations: Vec<Animation>,
+    /// frames the game has run, what `anim` plays animations by
+    pub clock: u64,
+    /// asked for by scripts during a frame, the runner makes and removes them once every object ran
+    pub spawns: Vec<Spawn>,
+    pub destroys: Vec<u64>,
+    /// sent with `send`, delivered to `on_message` once every object ran
+    pub messages: Vec<Message>,
+    /// shared by every object, see `global` and `setglobal`
+    pub globals: HashMap<String, WatchValue>,
+}
+/// what a script call can change about the game, see `ZVMState::save`
+pub struct Saved {
+    buffer: usize,
+    layer: usize,
+    pos: Pos2,
+    goto: Option<usize>,
+    spawns: usize,
+    destroys: usize,
+    messages: usize,
+    globals: HashMap<String, WatchValue>,
+    sprites: Vec<Sprite>,
+}
+/// a message `send` queued
+#[derive(Debug)]
+pub struct Message {
+    /// an object id, or a name or tag that picks every object that has it
+    pub target: WatchValue,
+    pub name: String,
+    pub value: WatchValue,
+    /// id of the sender
+    pub from: u64,
+}
+/// an object `spawn` asked for
+#[derive(Debug)]
+pub struct Spawn {
+    pub id: u64,
+    /// which `EditorObject` to copy
+    pub object: usize,
+    pub pos: Pos2,
+}
+impl ZVMState {
+    fn new(limits: Budget) -> ZVMState {
+        ZVMState {
+            buffer: vec![],
+            layer: 0,
+            keys: vec![],
+            mouse: None,
+            limits,
+            instructions: 0,
+            callstart: Instant::now(),
+            pos: Pos2::ZERO,
+            id: 0,
+            nextid: 0,
+            names: vec![],
+            scenes: vec![],
+            goto: None,
+            tags: vec![],
+            sprites: vec![],
+            map: Array2D::filled_with(None, 0, 0),
+            bodies: vec![],
+            animations: vec![],
+            clock: 0,
+            spawns: vec![],
+            destroys: vec![],
+            messages: vec![],
+            globals: HashMap::new(),
+            debug: Debugger::new(),
+        }
+    }
+    /// call before handing control to a script, resets the budget
+    pub fn startcall(&mut self) {
+        self.instructions = 0;
+        self.callstart = Instant::now();
+    }
+    /// call before running `entry` of an object's script, so its builtins know who they work for
+    pub fn begin(
+        &mut self,
+        index: usize,
+        editorobj: &EditorObject,
+        entry: &'static str,
+        pos: Pos2,
+        id: u64,
+    ) {
+        self.startcall();
+        self.debug.enter(index, &editorobj.name, entry);
+        self.pos = pos;
+        self.id = id;
+        self.layer = editorobj.drawlayer;
+    }
+    /// queues a draw event on the current layer
+    pub fn push(&mut self, event: ZEvent) {
+        self.buffer.push((self.layer, event));
+    }
+    /// an object by id. the one that's running is where its script just put it
+    pub fn body(&self, id: u64) -> Option<Body> {
+        let mut body = *self.bodies.iter().find(|b| b.id == id)?;
+        if id == self.id {
+            body.pos = self.pos;
+        }
+        Some(body)
+    }
+    /// `target` picks an object by id, or every object with that name or tag
+    pub fn targets(&self, target: &WatchValue, index: usize, id: u64) -> bool {
+        match target {
+            WatchValue::Number(n) => id == *n as u64,
+            WatchValue::String(s) => {
+                self.names.get(index) == Some(s)
+                    || self.tags.get(index).map_or(false, |t| t.contains(s))
+            }
+            _ => false,
+        }
+    }
+    /// what `restore` puts back, to run a script without it changing the game
+    pub fn save(&self) -> Saved {
+        Saved {
+            buffer: self.buffer.len(),
+            layer: self.layer,
+            pos: self.pos,
+            goto: self.goto,
+            spawns: self.spawns.len(),
+            destroys: self.destroys.len(),
+            messages: self.messages.len(),
+            globals: self.globals.clone(),
+            sprites: self.sprites.clone(),
+        }
+    }
+    /// drops what was queued since `save` and puts back what was changed.
+    /// ids handed out in between stay used, scripts may still hold them
+    pub fn restore(&mut self, saved: Saved) {
+        self.buffer.truncate(saved.buffer);
+        self.layer = saved.layer;
+        self.pos = saved.pos;
+        self.goto = saved.goto;
+        self.spawns.truncate(saved.spawns);
+        self.destroys.truncate(saved.destroys);
+        self.messages.truncate(saved.messages);
+        self.globals = saved.globals;
+        self.sprites = saved.sprites;
+    }
+    /// where errors in builtins go, see `NOWHERE`
+    pub fn here(&self) -> usize {
+        self.debug.at
+    }
+    pub fn newid(&mut self) -> u64 {
+        self.nextid += 1;
+        self.nextid
+    }
+    /// called by every builtin. once the budget runs out every builtin fails,
+    /// which unwinds the script back to whoever called it
+    pub fn tick(&mut self) -> Result<(), Exception> {
+        self.instructions += 1;
+        if self.instructions > self.limits.instructions {
+            return Err(Exception::new(
+                self.here(),
+                TIMEOUT_EXCEPTION,
+                &format!(
+                    "script ran more than {} instructions in one call",
+                    self.limits.instructions
+                ),
+            ));
+        }
+        if self.callstart.elapsed().as_millis() as u64 > self.limits.millis {
+            return Err(Exception::new(
+                self.here(),
+                TIMEOUT_EXCEPTION,
+                &format!(
+                    "script ran longer than {}ms in one call",
+                    self.limits.millis
+                ),
+            ));
+        }
+        Ok(())
+    }
 }
+pub const TIMEOUT_EXCEPTION: &str = "TimeoutException";
+/// where errors happened when it isn't known. builtins only know the line they're on in debug runs
+pub const NOWHERE: usize = usize::MAX;
 
-impl<'a> ZVM<'a> {
-    pub fn start(contents: String) -> Result<ZVM<'a>, Exception> {
-        match std::panic::catch_unwind(|| -> Result<ZVM<'a>, Exception> {
+impl ZVM {
+    /// `vars` are put in the root scope before the top level runs
+    pub fn start(
+        contents: String,
+        vars: HashMap<String, Value<'static>>,
+    ) -> Result<ZVM, Exception> {
+        match std::panic::catch_unwind(AssertUnwindSafe(|| -> Result<ZVM, Exception> {
             let tokens = lexer::lex(contents.clone());
             let libraryfunctions = consolebuiltins::functions();
 
-            let root = parser::parse(tokens, &contents, &libraryfunctions, &vec![])?;
+            let varnames: Vec<String> = vars.keys().cloned().collect();
+            let root = parser::parse(tokens, &contents, &libraryfunctions, &varnames)?;
             let mut functions = builtins::functions();
 
             for (k, v) in libraryfunctions {
@@ -68,16 +308,20 @@ impl<'a> ZVM<'a> {
                 );
             }
 
-            let scope = Rc::new(RefCell::new(
-                root.root.to_scope(ScopeType::Function, HashMap::new()),
-            ));
+            let vars = vars.into_iter().map(|(k, v)| (k, v.as_ref())).collect();
+            let scope = Rc::new(RefCell::new(root.root.to_scope(ScopeType::Function, vars)));
+            // so breakpoints in the top level can show it
+            with_state(|s| s.debug.show(&scope));
             runtime::run_root(scope.clone(), &functions, &contents)?;
             Ok(ZVM {
                 functions,
                 root_scope: scope,
+                source: contents.clone(),
                 contents: contents,
+                inserted: vec![],
+                debug: false,
             })
-        }) {
+        })) {
             Ok(o) => o,
             Err(_) => Err(Exception::new(
                 2,
@@ -87,43 +331,105 @@ impl<'a> ZVM<'a> {
         }
     }
 
+    /// like `start`, but the game can stop on any line, see `debugger`
+    pub fn start_debug(
+        source: String,
+        vars: HashMap<String, Value<'static>>,
+    ) -> Result<ZVM, Exception> {
+        let (contents, inserted) = debugger::instrument(&source);
+        match ZVM::start(contents, vars) {
+            Ok(mut vm) => {
+                vm.source = source;
+                vm.inserted = inserted;
+                vm.debug = true;
+                Ok(vm)
+            }
+            Err(mut e) => {
+                e.idx = debugger::unmap(e.idx, &inserted);
+                Err(e)
+            }
+        }
+    }
+    /// swaps in a new version of the script. the top level runs again to define everything,
+    /// then root scope variables that still exist get their old values back.
+    /// what the top level does to the game, like spawning or moving the object, is undone,
+    /// since it already happened when the object started. on error the old script keeps running
+    pub fn reload(
+        &mut self,
+        contents: String,
+        vars: HashMap<String, Value<'static>>,
+    ) -> Result<(), Exception> {
+        let saved = with_state(|s| s.save());
+        let fresh = if self.debug {
+            ZVM::start_debug(contents, vars)
+        } else {
+            ZVM::start(contents, vars)
+        };
+        with_state(|s| s.restore(saved));
+        let fresh = fresh?;
+        {
+            let old = self.root_scope.borrow();
+            let mut new = fresh.root_scope.borrow_mut();
+            for (name, value) in new.variables.iter_mut() {
+                if let Some(oldvalue) = old.variables.get(name) {
+                    *value = oldvalue.clone();
+                }
+            }
+        }
+        *self = fresh;
+        Ok(())
+    }
     pub fn draw(&mut self) -> Result<(), Exception> {
-        if let Some(drawfunc) = self.functions.get("draw") {
+        self.call("draw", vec![])
+    }
+    /// calls a function the script defined, if it did
+    pub fn call(&mut self, name: &str, args: Vec<Value<'static>>) -> Result<(), Exception> {
+        if let Some(func) = self.functions.get(name) {
+            with_state(|s| s.debug.show(&self.root_scope));
             self.root_scope.borrow_mut().call_function(
-                drawfunc,
-                vec![],
+                func,
+                args,
                 &self.functions,
                 &self.contents,
             )?;
         }
         Ok(())
     }
-    pub fn fmt(&self, exception: Exception) -> String {
-        errfmt(exception, &self.contents)
+    pub fn fmt(&self, mut exception: Exception) -> String {
+        if exception.idx != NOWHERE {
+            exception.idx = debugger::unmap(exception.idx, &self.inserted);
+        }
+        errfmt(exception, &self.source)
     }
 }
 pub fn errfmt(exception: Exception, input: &String) -> String {
-    let mut i = 0;
+    if exception.idx == NOWHERE {
+        let line = format!("{} {}", "ERROR:", exception.message);
+        let dashes = "-".repeat(line.len() / 2);
+        return format!(
+            "{}\n      {}\n{}\n{}",
+            dashes, exception.errtype, line, dashes
+        );
+    }
     let mut lines = 0;
-    let mut offset = 0;
-    while i < exception.idx {
-        if input.chars().nth(i).unwrap() == '\n' {
+    let mut offset: usize = 0;
+    for c in input.chars().take(exception.idx) {
+        if c == '\n' {
             lines += 1;
             offset = 0;
         }
         offset += 1;
-        i += 1;
     }
     let allines: Vec<&str> = input.lines().collect();
 
     let line1 = format!(
         "      \"{}\"     {}",
-        allines[lines],
+        allines.get(lines).unwrap_or(&""),
         format!("at line {}, col {}", lines.to_string(), offset.to_string())
     );
     let line2 = format!(
         "      {}{}      {}",
-        " ".repeat(offset - 1),
+        " ".repeat(offset.saturating_sub(1)),
         "^",
         exception.errtype
     );
@@ -141,3 +447,69 @@ pub fn errfmt(exception: Exception, input: &String) -> String {
         "-".repeat(dasheslen)
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restore_undoes_what_was_queued_since_save() {
+        initstate(Budget::default());
+        let saved = with_state(|s| {
+            s.push(ZEvent::Put("kept".to_string()));
+            s.save()
+        });
+        with_state(|s| {
+            s.push(ZEvent::Put("dropped".to_string()));
+            s.pos = egui::pos2(5.0, 5.0);
+            let id = s.newid();
+            s.spawns.push(Spawn {
+                id,
+                object: 0,
+                pos: Pos2::ZERO,
+            });
+            s.goto = Some(1);
+            s.globals
+                .insert("score".to_string(), WatchValue::Number(1.0));
+            s.restore(saved);
+            assert_eq!(s.buffer.len(), 1);
+            assert_eq!(s.pos, Pos2::ZERO);
+            assert!(s.spawns.is_empty() && s.goto.is_none() && s.globals.is_empty());
+            // the id stays used
+            assert_eq!(s.newid(), 2);
+        });
+    }
+
+    #[test]
+    fn reload_runs_top_level_spawns_once() {
+        initstate(Budget::default());
+        with_state(|s| s.names = vec!["coin".to_string()]);
+        let script = "spawn(\"coin\", 1, 2)\nfn draw() {\n}";
+        let mut vm = ZVM::start(script.to_string(), HashMap::new()).unwrap();
+        assert_eq!(with_state(|s| s.spawns.len()), 1);
+        let script = "spawn(\"coin\", 1, 2)\nsetpos(9, 9)\nfn draw() {\n  put(1)\n}";
+        vm.reload(script.to_string(), HashMap::new()).unwrap();
+        with_state(|s| {
+            assert_eq!(s.spawns.len(), 1);
+            assert_eq!(s.pos, Pos2::ZERO);
+        });
+    }
+
+    #[test]
+    fn errfmt_points_at_the_line() {
+        let source = "a = 1\nb = c".to_string();
+        let out = errfmt(Exception::new(10, "E", "no c"), &source);
+        assert!(out.contains("\"b = c\""), "{}", out);
+        assert!(out.contains("ERROR: no c"), "{}", out);
+    }
+
+    #[test]
+    fn errfmt_survives_positions_it_cant_show() {
+        let source = "a = 1".to_string();
+        let out = errfmt(Exception::new(NOWHERE, "E", "lost"), &source);
+        assert!(!out.contains("a = 1"), "{}", out);
+        assert!(out.contains("ERROR: lost"), "{}", out);
+        let out = errfmt(Exception::new(99, "E", "past the end"), &source);
+        assert!(out.contains("ERROR: past the end"), "{}", out);
+    }
+}